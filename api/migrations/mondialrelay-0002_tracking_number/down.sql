@@ -0,0 +1,2 @@
+DROP INDEX shipments_tracking_number_idx;
+ALTER TABLE shipments DROP COLUMN tracking_number;
@@ -0,0 +1,5 @@
+ALTER TABLE shipments ADD COLUMN tracking_number TEXT;
+-- tracking number of existing shipments is in the expedition query of the label url.
+UPDATE shipments SET tracking_number = substring(label_url FROM '[?&]expedition=([^&]+)');
+ALTER TABLE shipments ALTER COLUMN tracking_number SET NOT NULL;
+CREATE INDEX shipments_tracking_number_idx ON shipments (tracking_number);
@@ -0,0 +1,12 @@
+-- a key given by the client can only create one shipment.
+CREATE TABLE idempotency_keys (
+  -- tenant the key was given for, empty for the default profile: the keys of the tenants are kept apart.
+  tenant TEXT NOT NULL DEFAULT '',
+  key TEXT NOT NULL,
+  order_id INT NOT NULL,
+  shipment_id INT REFERENCES shipments (id) ON DELETE CASCADE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  -- tracking number given by Mondial Relay, kept even if the shipment could not be saved.
+  tracking_number TEXT,
+  PRIMARY KEY (tenant, key)
+);
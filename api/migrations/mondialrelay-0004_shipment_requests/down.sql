@@ -0,0 +1 @@
+DROP TABLE shipment_requests;
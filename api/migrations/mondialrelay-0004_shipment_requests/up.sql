@@ -0,0 +1,12 @@
+-- every request sent to Mondial Relay to create a shipment, successful or not.
+CREATE TABLE shipment_requests (
+  id SERIAL PRIMARY KEY,
+  order_id INT NOT NULL,
+  shipment_id INT REFERENCES shipments (id) ON DELETE SET NULL,
+  request_xml TEXT NOT NULL,
+  response_xml TEXT,
+  http_status INT,
+  error TEXT,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+CREATE INDEX shipment_requests_order_id_idx ON shipment_requests (order_id);
@@ -0,0 +1,4 @@
+DELETE FROM shipments WHERE label_url IS NULL;
+ALTER TABLE shipments DROP COLUMN label_code;
+ALTER TABLE shipments DROP COLUMN label_type;
+ALTER TABLE shipments ALTER COLUMN label_url SET NOT NULL;
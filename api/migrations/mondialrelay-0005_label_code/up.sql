@@ -0,0 +1,4 @@
+-- labels for thermal printers are given as code instead of url.
+ALTER TABLE shipments ALTER COLUMN label_url DROP NOT NULL;
+ALTER TABLE shipments ADD COLUMN label_type TEXT NOT NULL DEFAULT 'PdfUrl';
+ALTER TABLE shipments ADD COLUMN label_code TEXT;
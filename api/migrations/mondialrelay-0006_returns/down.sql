@@ -0,0 +1,4 @@
+DELETE FROM shipments WHERE is_return;
+DROP INDEX shipments_return_of_idx;
+ALTER TABLE shipments DROP COLUMN return_of;
+ALTER TABLE shipments DROP COLUMN is_return;
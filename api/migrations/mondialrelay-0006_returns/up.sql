@@ -0,0 +1,5 @@
+-- return labels, from the recipient back to the sender, are linked to the original shipment.
+ALTER TABLE shipments ADD COLUMN is_return BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE shipments ADD COLUMN return_of INT REFERENCES shipments (id) ON DELETE SET NULL;
+-- a shipment has one return at most, even with concurrent requests.
+CREATE UNIQUE INDEX shipments_return_of_idx ON shipments (return_of) WHERE is_return;
@@ -0,0 +1,2 @@
+DROP TABLE shipment_events;
+ALTER TABLE shipments DROP COLUMN tracking_status;
@@ -0,0 +1,15 @@
+-- last status given by the tracing of Mondial Relay, NULL until the first tracing.
+ALTER TABLE shipments ADD COLUMN tracking_status INT;
+-- events of the tracing of a shipment, as given by Mondial Relay.
+CREATE TABLE shipment_events (
+  id SERIAL PRIMARY KEY,
+  shipment_id INT NOT NULL REFERENCES shipments (id) ON DELETE CASCADE,
+  label TEXT NOT NULL,
+  -- local time of Mondial Relay
+  happened_at TIMESTAMP NOT NULL,
+  location TEXT,
+  relay TEXT,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  -- the whole history is given at each tracing.
+  UNIQUE (shipment_id, happened_at, label)
+);
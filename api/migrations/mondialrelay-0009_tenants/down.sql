@@ -0,0 +1,2 @@
+DROP INDEX shipments_tenant_order_id_idx;
+ALTER TABLE shipments DROP COLUMN tenant;
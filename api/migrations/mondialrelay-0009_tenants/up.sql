@@ -0,0 +1,3 @@
+-- brand profile of the configuration used to create the shipment, NULL for the default one.
+ALTER TABLE shipments ADD COLUMN tenant TEXT;
+CREATE INDEX shipments_tenant_order_id_idx ON shipments (tenant, order_id);
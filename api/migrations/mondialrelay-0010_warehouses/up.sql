@@ -0,0 +1,2 @@
+-- warehouse of the configuration the parcels left from, NULL for the address of the sender.
+ALTER TABLE shipments ADD COLUMN warehouse TEXT;
@@ -1,11 +1,26 @@
 use serde::{Deserialize, Serialize};
-use std::{error::Error, path::PathBuf};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+};
 use url::Url;
+use xsd_parser::generator::validator::Validate;
 
-use crate::request::{
-    Address, Context,
-    address_type::CountryCode,
-    context_type::{Culture, CustomerId, VersionAPI},
+use crate::{
+    request::{
+        Address, Context, OutputOptions, ProductConfiguration,
+        address_type::CountryCode,
+        context_type::{Culture, CustomerId, VersionAPI},
+        output_options_type::LabelType,
+        product_configuration_type::CollectionMode,
+    },
+    storage::StorageConfig,
+    tracker::TrackerConfig,
+    webhook::WebhooksConfig,
+    webservice::WebServiceConfig,
 };
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -13,20 +28,95 @@ pub struct Config {
     // cover database connection
     pub db_uri: Url,
     pub db_pass_path: PathBuf,
-    // port on which the cover API will listen for incoming connections
+    // address and port on which the cover API will listen for incoming connections
+    #[serde(default = "default_listen_address")]
+    pub listen_address: IpAddr,
     pub listen_port: u16,
     // logins for mondialrelay
     pub brand_id: String,
     pub password_path: PathBuf,
+    // logins used in test mode
+    #[serde(default = "default_brand_id_test")]
+    pub brand_id_test: String,
     pub password_path_test: PathBuf,
-    // Mondial Relay language of printed label. en-EN format.
+    // Mondial Relay language of printed label. en-GB format.
     pub culture: String,
-    // Mondial Relay label output: A4, A5, 10x15
+    // Mondial Relay label output: A4, A5, 10x15 for PdfUrl,
+    // printer model (Monarch9855, MiniMonarch9416XL) for ZplCode and IplCode.
     pub format: String,
+    // Mondial Relay label type: PdfUrl, ZplCode or IplCode
+    #[serde(default)]
+    pub output_type: LabelType,
     // sender details
     pub address_sender: AddressBusiness,
+    // other places the parcels leave from, selected by name for each shipment. Shared by the tenants.
+    #[serde(default)]
+    pub warehouses: BTreeMap<String, AddressBusiness>,
+    // warehouse of the shipments that don't give one, address_sender if none.
+    #[serde(default)]
+    pub default_warehouse: Option<String>,
+    // send the order and customer numbers of the shipments to Mondial Relay, shown on its invoices.
+    #[serde(default)]
+    pub share_references: bool,
+    // how Mondial Relay collects the parcels: CCC at the sender, REL at its Point Relais
+    #[serde(default)]
+    pub collection_mode: CollectionMode,
     // are we in test mode ?
     pub test: bool,
+    // base url of the Mondial Relay API, must end with a slash.
+    #[serde(default = "default_api_url")]
+    pub api_url: Url,
+    // base url of the Mondial Relay API in test mode.
+    // sandbox doesn't work currently (https://connect-api-sandbox.mondialrelay.com/api/),
+    // so the production API is used with the test credentials.
+    #[serde(default = "default_api_url")]
+    pub api_url_sandbox: Url,
+    // where the pdf of the labels are kept
+    #[serde(default)]
+    pub label_storage: StorageConfig,
+    // web service of Mondial Relay, for the tracing of the parcels
+    #[serde(default)]
+    pub web_service: WebServiceConfig,
+    // background tracing of the shipments
+    #[serde(default)]
+    pub tracker: TrackerConfig,
+    // notifications of the events of the shipments
+    #[serde(default)]
+    pub webhooks: WebhooksConfig,
+    // profiles of other brands, selected by name for each request.
+    // The brand, credentials and sender above are used without a name.
+    #[serde(default)]
+    pub tenants: BTreeMap<String, TenantConfig>,
+}
+
+/// Brand account of Mondial Relay with its own sender, selected per request.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct TenantConfig {
+    pub brand_id: String,
+    pub password_path: PathBuf,
+    // logins used in test mode, needed then so the tenants don't share the test account.
+    pub brand_id_test: Option<String>,
+    pub password_path_test: Option<PathBuf>,
+    pub address_sender: AddressBusiness,
+    // warehouse of its shipments that don't give one, its address_sender if none.
+    pub default_warehouse: Option<String>,
+    // default to the values of the default profile
+    pub culture: Option<String>,
+    pub format: Option<String>,
+    pub output_type: Option<LabelType>,
+    pub web_service: Option<WebServiceConfig>,
+}
+
+fn default_listen_address() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::LOCALHOST)
+}
+
+fn default_brand_id_test() -> String {
+    String::from("BDTEST")
+}
+
+fn default_api_url() -> Url {
+    Url::parse("https://connect-api.mondialrelay.com/api/").unwrap()
 }
 
 #[derive(Deserialize, Serialize, Clone, Default, Debug)]
@@ -43,6 +133,9 @@ pub struct AddressBusiness {
     // specify the area code (e.g. +33 for FRANCE).
     pub phone_no: String,
     pub email: String,
+    // Point Relais where the parcels are dropped off for the REL collection mode (e. g. FR-12345)
+    #[serde(default)]
+    pub collection_relay: Option<String>,
 }
 
 impl Default for Config {
@@ -51,34 +144,317 @@ impl Default for Config {
         Self {
             db_uri: Url::parse("postgresql://user@127.0.0.1:5432/mydb").unwrap(),
             db_pass_path: PathBuf::from("name_api/db/user"),
+            listen_address: default_listen_address(),
             listen_port: 10200,
             brand_id: String::from("BDTEST"),
+            brand_id_test: default_brand_id_test(),
             password_path_test: PathBuf::from("mondialrelay_api_test"),
             password_path: PathBuf::from("mondialrelay_api"),
             culture: String::from("fr-FR"),
             format: "A4".to_string(),
+            output_type: LabelType::PdfUrl,
             // todo example address
             address_sender: AddressBusiness::default(),
+            warehouses: BTreeMap::new(),
+            default_warehouse: None,
+            share_references: false,
+            collection_mode: CollectionMode::Merchant,
             test: true,
+            api_url: default_api_url(),
+            api_url_sandbox: default_api_url(),
+            label_storage: StorageConfig::default(),
+            web_service: WebServiceConfig::default(),
+            tracker: TrackerConfig::default(),
+            webhooks: WebhooksConfig::default(),
+            tenants: BTreeMap::new(),
         }
     }
 }
 
+/// prefix of the environment variables overriding the configuration.
+pub const ENV_PREFIX: &str = "MONDIALRELAY_";
+/// longest interval or backoff in seconds, the dates computed from longer ones would overflow.
+const MAX_SECONDS: u64 = 365 * 86_400;
+/// longest age in days of a shipment followed by the tracker.
+const MAX_AGE: u32 = 36_500;
+
+/// problem of a period in seconds: zero panics, a long one overflows the dates computed from it.
+fn period_problem(name: &str, seconds: u64) -> Option<String> {
+    (!(1..=MAX_SECONDS).contains(&seconds))
+        .then(|| format!("{name} must be between 1 and {MAX_SECONDS} seconds, {seconds} given"))
+}
+
+/// Where the configuration comes from, to load it again on reload.
+#[derive(Clone, Debug)]
+pub struct ConfigSource {
+    pub path: PathBuf,
+    // overrides given to the command line, as environment variables.
+    pub arguments: Vec<(String, String)>,
+}
+
+impl ConfigSource {
+    /// read the file, then apply the environment variables and the arguments.
+    /// The file must exist, a wrong path must not load the default values.
+    pub fn load(&self) -> Result<Config, Box<dyn Error>> {
+        if !self.path.exists() {
+            return Err(format!(
+                "The configuration file {} does not exist",
+                self.path.display()
+            )
+            .into());
+        }
+        Ok(Config::load(&self.path)?.with_overrides(self.arguments.clone())?)
+    }
+}
+
 impl Config {
+    /// load the configuration file, created with the default values if it doesn't exist,
+    /// then override its values with the environment variables.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let config: Config = confy::load_path(path)?;
+        Ok(config.with_overrides(std::env::vars())?)
+    }
+    /// override the values of the configuration with the variables prefixed by MONDIALRELAY_.
+    /// The name of the field follows the prefix in uppercase, nested fields are separated by a double underscore:
+    /// MONDIALRELAY_LISTEN_PORT=10201, MONDIALRELAY_ADDRESS_SENDER__CITY=Dijon.
+    /// Values are taken as is for text fields, and parsed as json for others (true, 3600, ["delivered"]).
+    pub fn with_overrides(
+        self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, String> {
+        let mut config = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        for (name, raw) in vars {
+            let Some(path) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            // path of the configuration file, given to the command line.
+            if path == "CONFIG" {
+                continue;
+            }
+            let mut field = &mut config;
+            for key in path.to_lowercase().split("__") {
+                field = field.get_mut(key).ok_or(format!(
+                    "{name} does not match a field of the configuration"
+                ))?;
+            }
+            *field = match field {
+                Value::String(_) => Value::String(raw),
+                _ => serde_json::from_str(&raw).unwrap_or(Value::String(raw)),
+            };
+        }
+        serde_json::from_value(config).map_err(|e| format!("Invalid override: {e}"))
+    }
+    /// check everything that would only fail at the first shipment:
+    /// the context and sender sent to Mondial Relay, the label output and the secrets.
+    /// Returns every problem found.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = self.profile_problems();
+        for (name, profile) in &self.tenants {
+            if self.test
+                && (profile.brand_id_test.is_none() || profile.password_path_test.is_none())
+            {
+                problems.push(format!(
+                    "Tenant {name}: brand_id_test and password_path_test are needed in test mode"
+                ));
+            }
+            let tenant = self.tenant(name).expect("tenant should exist");
+            problems.extend(
+                tenant
+                    .profile_problems()
+                    .into_iter()
+                    .map(|problem| format!("Tenant {name}: {problem}")),
+            );
+        }
+        for name in self.warehouses.keys() {
+            let warehouse = self.warehouse(name).expect("warehouse should exist");
+            if let Err(e) = warehouse.sender_address().validate() {
+                problems.push(format!("Invalid address of the warehouse {name}: {e}"));
+            }
+            if let Err(e) = warehouse.collection(self.collection_mode) {
+                problems.push(format!("Warehouse {name}: {e}"));
+            }
+        }
+        problems.extend(period_problem("tracker.interval", self.tracker.interval));
+        problems.extend(period_problem("webhooks.interval", self.webhooks.interval));
+        // doubled up to 2^16 times for the retries.
+        problems.extend(period_problem("webhooks.backoff", self.webhooks.backoff));
+        if self.tracker.max_age > MAX_AGE {
+            problems.push(format!(
+                "tracker.max_age must be at most {MAX_AGE} days, {} given",
+                self.tracker.max_age
+            ));
+        }
+        for url in [&self.api_url, &self.api_url_sandbox] {
+            if !url.path().ends_with('/') {
+                problems.push(format!("The url {url} of the API must end with a slash"));
+            }
+        }
+        if let Err(e) = get_pass::get_password(&self.db_pass_path) {
+            problems.push(format!("Could not read the password of the database: {e}"));
+        }
+        for subscription in &self.webhooks.subscriptions {
+            if let Err(e) = get_pass::get_password(&subscription.secret_path) {
+                problems.push(format!(
+                    "Could not read the secret of the webhook {}: {e}",
+                    subscription.url
+                ));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+    // problems of the settings that a tenant can change.
+    fn profile_problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        // the context is checked without its password, which is read after.
+        let context = self.context_api_mondialrelay_with(String::new());
+        if let Err(e) = context.customer_id.validate() {
+            problems.push(format!("Invalid brand_id: {e}"));
+        }
+        if let Err(e) = context.culture.validate() {
+            problems.push(format!("Invalid culture: {e}"));
+        }
+        if let Err(e) = get_pass::get_password(self.password_path()) {
+            problems.push(format!("Could not read the password of the API: {e}"));
+        }
+        if let Err(e) = self.sender_address().validate() {
+            problems.push(format!("Invalid address_sender: {e}"));
+        }
+        if let Some(name) = &self.default_warehouse
+            && !self.warehouses.contains_key(name)
+        {
+            problems.push(format!("The default warehouse {name} does not exist"));
+        }
+        if let Err(e) = OutputOptions::new(self.output_type, self.format.clone()).validate() {
+            problems.push(format!("Invalid output_type or format: {e}"));
+        }
+        if let Err(e) = self.collection(self.collection_mode) {
+            problems.push(e);
+        }
+        if (self.tracker.enabled || self.web_service.check_relay)
+            && let Err(e) = get_pass::get_password(&self.web_service.private_key_path)
+        {
+            problems.push(format!(
+                "Could not read the private key of the web service: {e}"
+            ));
+        }
+        problems
+    }
+    /// configuration of the tenant with this name, None if there is none.
+    /// Settings that the tenant doesn't give are taken from the default profile.
+    pub fn tenant(&self, name: &str) -> Option<Config> {
+        let tenant = self.tenants.get(name)?.clone();
+        let mut config = self.clone();
+        config.tenants.clear();
+        config.brand_id = tenant.brand_id;
+        config.password_path = tenant.password_path;
+        if let Some(brand_id_test) = tenant.brand_id_test {
+            config.brand_id_test = brand_id_test;
+        }
+        if let Some(password_path_test) = tenant.password_path_test {
+            config.password_path_test = password_path_test;
+        }
+        config.address_sender = tenant.address_sender;
+        // the default warehouse of the default profile would hide the sender of the tenant.
+        config.default_warehouse = tenant.default_warehouse;
+        if let Some(culture) = tenant.culture {
+            config.culture = culture;
+        }
+        if let Some(format) = tenant.format {
+            config.format = format;
+        }
+        if let Some(output_type) = tenant.output_type {
+            config.output_type = output_type;
+        }
+        if let Some(web_service) = tenant.web_service {
+            config.web_service = web_service;
+        }
+        Some(config)
+    }
+    /// the new configuration to apply live, with the settings only read at startup kept from this one.
+    /// Returns also the settings changed that need a restart.
+    pub fn reloaded(&self, mut new: Config) -> (Config, Vec<&'static str>) {
+        let changed = [
+            ("listen_address", self.listen_address != new.listen_address),
+            ("listen_port", self.listen_port != new.listen_port),
+            ("db_uri", self.db_uri != new.db_uri),
+            ("db_pass_path", self.db_pass_path != new.db_pass_path),
+            ("label_storage", self.label_storage != new.label_storage),
+            (
+                "tracker.interval",
+                self.tracker.interval != new.tracker.interval,
+            ),
+            (
+                "webhooks.interval",
+                self.webhooks.interval != new.webhooks.interval,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(field, changed)| changed.then_some(field))
+        .collect();
+        new.listen_address = self.listen_address;
+        new.listen_port = self.listen_port;
+        new.db_uri = self.db_uri.clone();
+        new.db_pass_path = self.db_pass_path.clone();
+        new.label_storage = self.label_storage.clone();
+        new.tracker.interval = self.tracker.interval;
+        new.webhooks.interval = self.webhooks.interval;
+        (new, changed)
+    }
+    /// configuration sending from the warehouse with this name, None if there is none.
+    pub fn warehouse(&self, name: &str) -> Option<Config> {
+        let mut config = self.clone();
+        config.address_sender = self.warehouses.get(name)?.clone();
+        Some(config)
+    }
     pub fn context_api_mondialrelay(&self) -> Result<Context, Box<dyn Error>> {
-        let brand_id = if self.test { "BDTEST" } else { &self.brand_id };
-        let login = [brand_id, "@business-api.mondialrelay.com"].concat();
-        let pass_path = if self.test {
-            &self.password_path_test
+        Ok(self.context_api_mondialrelay_with(get_pass::get_password(self.password_path())?))
+    }
+    fn context_api_mondialrelay_with(&self, password: String) -> Context {
+        let brand_id = if self.test {
+            &self.brand_id_test
         } else {
-            &self.password_path
+            &self.brand_id
         };
-        Ok(Context {
-            login,
-            password: get_pass::get_password(pass_path)?,
+        Context {
+            login: [brand_id, "@business-api.mondialrelay.com"].concat(),
+            password,
             customer_id: CustomerId(brand_id.to_string()),
             culture: Culture(self.culture.clone()),
             version_api: VersionAPI("1.0".to_string()),
+        }
+    }
+    // pass path of the password of the API, depending on test mode.
+    fn password_path(&self) -> &Path {
+        if self.test {
+            &self.password_path_test
+        } else {
+            &self.password_path
+        }
+    }
+    /// url of the Mondial Relay API to create shipments, depending on test mode.
+    pub fn shipment_url(&self) -> Result<Url, url::ParseError> {
+        let base = if self.test {
+            &self.api_url_sandbox
+        } else {
+            &self.api_url
+        };
+        base.join("shipment")
+    }
+    /// collection of the parcels of the sender with this mode.
+    pub fn collection(&self, mode: CollectionMode) -> Result<ProductConfiguration, String> {
+        let location = match mode {
+            CollectionMode::Merchant => None,
+            CollectionMode::Relay => Some(self.address_sender.collection_relay.clone().ok_or(
+                "Collection mode REL needs the collection_relay of the sender in the configuration",
+            )?),
+        };
+        Ok(ProductConfiguration {
+            mode: mode.as_str().to_string(),
+            location,
         })
     }
     pub fn sender_address(&self) -> Address {
@@ -3,20 +3,116 @@
 #![allow(unused)]
 #![allow(clippy::all)]
 
-use chrono::DateTime;
 use chrono::offset::Utc;
+use chrono::{DateTime, NaiveDateTime};
 use diesel::{
     Queryable, Selectable,
     prelude::{AsChangeset, Associations, Identifiable, Insertable},
 };
-#[derive(Queryable, Debug, Selectable, Insertable, Identifiable, PartialEq, Default)]
+use serde::Serialize;
+#[derive(Queryable, Debug, Selectable, Insertable, Identifiable, PartialEq, Default, Serialize)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 #[diesel(table_name = crate::db::schema::shipments)]
 pub struct Shipment {
     #[diesel(skip_insertion)]
     pub id: i32,
     pub order_id: i32,
-    pub label_url: String,
+    // set for PdfUrl labels
+    pub label_url: Option<String>,
+    #[diesel(skip_insertion)]
+    #[diesel(deserialize_as = DateTime<Utc>)]
+    pub created_at: Option<DateTime<Utc>>,
+    pub tracking_number: String,
+    // PdfUrl, ZplCode or IplCode
+    pub label_type: String,
+    // set for ZplCode and IplCode labels
+    pub label_code: Option<String>,
+    // label for the recipient to send the parcels back
+    pub is_return: bool,
+    // original shipment of a return
+    pub return_of: Option<i32>,
+    // last status of the tracing of Mondial Relay
+    pub tracking_status: Option<i32>,
+    // brand profile of the configuration, None for the default one
+    pub tenant: Option<String>,
+    // warehouse the parcels left from, None for the address of the sender
+    pub warehouse: Option<String>,
+}
+
+#[derive(Queryable, Debug, Selectable, Insertable, Identifiable, PartialEq, Default)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(table_name = crate::db::schema::idempotency_keys)]
+#[diesel(primary_key(tenant, key))]
+pub struct IdempotencyKey {
+    // tenant the key was given for, empty for the default profile
+    pub tenant: String,
+    pub key: String,
+    pub order_id: i32,
+    // set once the shipment is created
+    pub shipment_id: Option<i32>,
+    #[diesel(skip_insertion)]
+    #[diesel(deserialize_as = DateTime<Utc>)]
+    pub created_at: Option<DateTime<Utc>>,
+    // set as soon as Mondial Relay created the shipment, before it is saved
+    pub tracking_number: Option<String>,
+}
+
+// exchange with Mondial Relay for the creation of a shipment, kept for investigation.
+#[derive(Queryable, Debug, Selectable, Insertable, Identifiable, PartialEq, Default, Clone)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(table_name = crate::db::schema::shipment_requests)]
+pub struct ShipmentRequest {
+    #[diesel(skip_insertion)]
+    pub id: i32,
+    pub order_id: i32,
+    // set if the shipment was created
+    pub shipment_id: Option<i32>,
+    // credentials are redacted
+    pub request_xml: String,
+    pub response_xml: Option<String>,
+    pub http_status: Option<i32>,
+    pub error: Option<String>,
+    #[diesel(skip_insertion)]
+    #[diesel(deserialize_as = DateTime<Utc>)]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+// event of the tracing of a shipment by Mondial Relay.
+#[derive(Queryable, Debug, Selectable, Insertable, Identifiable, PartialEq, Default, Serialize)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(table_name = crate::db::schema::shipment_events)]
+pub struct ShipmentEvent {
+    #[diesel(skip_insertion)]
+    pub id: i32,
+    pub shipment_id: i32,
+    pub label: String,
+    // local time of Mondial Relay
+    pub happened_at: NaiveDateTime,
+    pub location: Option<String>,
+    // Point Relais of the event, with its country
+    pub relay: Option<String>,
+    #[diesel(skip_insertion)]
+    #[diesel(deserialize_as = DateTime<Utc>)]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+// notification of a webhook, kept until it is delivered.
+#[derive(Queryable, Debug, Selectable, Insertable, Identifiable, PartialEq, Default)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(table_name = crate::db::schema::webhook_deliveries)]
+pub struct WebhookDelivery {
+    #[diesel(skip_insertion)]
+    pub id: i32,
+    pub url: String,
+    pub event: String,
+    // json body of the notification
+    pub payload: String,
+    pub attempts: i32,
+    #[diesel(skip_insertion)]
+    #[diesel(deserialize_as = DateTime<Utc>)]
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<DateTime<Utc>>,
     #[diesel(skip_insertion)]
     #[diesel(deserialize_as = DateTime<Utc>)]
     pub created_at: Option<DateTime<Utc>>,
@@ -1,10 +1,80 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    idempotency_keys (tenant, key) {
+        tenant -> Text,
+        key -> Text,
+        order_id -> Int4,
+        shipment_id -> Nullable<Int4>,
+        created_at -> Timestamptz,
+        tracking_number -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    shipment_events (id) {
+        id -> Int4,
+        shipment_id -> Int4,
+        label -> Text,
+        happened_at -> Timestamp,
+        location -> Nullable<Text>,
+        relay -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    shipment_requests (id) {
+        id -> Int4,
+        order_id -> Int4,
+        shipment_id -> Nullable<Int4>,
+        request_xml -> Text,
+        response_xml -> Nullable<Text>,
+        http_status -> Nullable<Int4>,
+        error -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     shipments (id) {
         id -> Int4,
         order_id -> Int4,
-        label_url -> Text,
+        label_url -> Nullable<Text>,
         created_at -> Timestamptz,
+        tracking_number -> Text,
+        label_type -> Text,
+        label_code -> Nullable<Text>,
+        is_return -> Bool,
+        return_of -> Nullable<Int4>,
+        tracking_status -> Nullable<Int4>,
+        tenant -> Nullable<Text>,
+        warehouse -> Nullable<Text>,
     }
 }
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Int4,
+        url -> Text,
+        event -> Text,
+        payload -> Text,
+        attempts -> Int4,
+        next_attempt_at -> Timestamptz,
+        last_error -> Nullable<Text>,
+        delivered_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::joinable!(idempotency_keys -> shipments (shipment_id));
+diesel::joinable!(shipment_events -> shipments (shipment_id));
+diesel::joinable!(shipment_requests -> shipments (shipment_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    idempotency_keys,
+    shipment_events,
+    shipment_requests,
+    shipments,
+    webhook_deliveries,
+);
@@ -1,36 +1,256 @@
-use axum::http::StatusCode;
-use axum_thiserror::ErrorStatus;
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
 use deadpool_diesel::{InteractError, PoolError};
+use serde::Serialize;
 use thiserror::Error;
 
-#[derive(Debug, Error, ErrorStatus)]
+use crate::response::Status;
+
+#[derive(Debug, Error)]
 pub enum AppError {
     #[error("API returned an error")]
-    #[status(StatusCode::INTERNAL_SERVER_ERROR)]
     PgError(#[from] PoolError),
     #[error("API returned an error")]
-    #[status(StatusCode::INTERNAL_SERVER_ERROR)]
     DeadPoolError(#[from] InteractError),
     #[error("API returned an error")]
-    #[status(StatusCode::INTERNAL_SERVER_ERROR)]
     DieselError(#[from] diesel::result::Error),
     #[error("Misconfigured cover API on server side")]
-    #[status(axum::http::StatusCode::INTERNAL_SERVER_ERROR)]
     Conf,
+    #[error("The configuration can not be reloaded: {0}")]
+    InvalidConfig(String),
     #[error("Request xml content is not valid from the mondial relay schema: {0}")]
-    #[status(axum::http::StatusCode::INTERNAL_SERVER_ERROR)]
     Xml(String),
     #[error("Response xml from mondialrelay does not contains the label: {0}")]
-    #[status(axum::http::StatusCode::INTERNAL_SERVER_ERROR)]
     NoLabel(String),
     /// The API response status code is an error.
     #[error(transparent)]
-    #[status(axum::http::StatusCode::INTERNAL_SERVER_ERROR)]
     ReqwestError(#[from] reqwest::Error),
+    #[error("API returned an error")]
+    Storage(#[from] std::io::Error),
+    #[error("A task of the server failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+    #[error("Could not make the pdf of the labels: {0}")]
+    Pdf(String),
     #[error("The order does not exist.")]
-    #[status(axum::http::StatusCode::BAD_REQUEST)]
     OrderNotFound,
+    #[error("The order has no label for printers.")]
+    NoLabelCode,
+    #[error("No shipment has this tracking number.")]
+    TrackingNotFound,
+    #[error("The shipment does not exist.")]
+    ShipmentNotFound,
+    #[error("No tenant is named {0} in the configuration.")]
+    UnknownTenant(String),
+    #[error("The tenant of the shipment is not the one of the header: {0}")]
+    TenantMismatch(String),
+    #[error("No warehouse is named {0} in the configuration.")]
+    UnknownWarehouse(String),
     #[error("The address is incorrect: {0}")]
-    #[status(axum::http::StatusCode::BAD_REQUEST)]
     BadAddress(String),
+    #[error("The relay is incorrect: {0}")]
+    InvalidRelay(String),
+    #[error("The relay {0} is closed.")]
+    RelayClosed(String),
+    #[error("The post code is incorrect: {0}")]
+    InvalidPostCode(String),
+    #[error("The delivery or collection mode is incorrect: {0}")]
+    InvalidMode(String),
+    #[error("The weight is out of range: {0}")]
+    WeightOutOfRange(String),
+    #[error("The parcels are incorrect: {0}")]
+    InvalidParcel(String),
+    #[error("The label output is incorrect: {0}")]
+    BadOutput(String),
+    #[error("The value or options of the shipment are incorrect: {0}")]
+    InvalidOption(String),
+    #[error("The order or customer number is incorrect: {0}")]
+    InvalidReference(String),
+    #[error("The selection of labels is incorrect: {0}")]
+    BadSelection(String),
+    #[error("A return can not be created for this order: {0}")]
+    ReturnImpossible(String),
+    #[error("Mondial Relay refused the credentials of the server: {0}")]
+    Authentication(String),
+    #[error("Mondial Relay rejected the shipment with code {code}: {message}")]
+    Rejected { code: String, message: String },
+    #[error("Mondial Relay is unavailable: {0}")]
+    Unavailable(String),
+    #[error("Mondial Relay could not handle the request: {0}")]
+    ServiceError(String),
+    #[error("The body of the request is incorrect: {0}")]
+    InvalidBody(String),
+    #[error("The idempotency key is incorrect: {0}")]
+    BadIdempotencyKey(String),
+    #[error("The idempotency key was already used for another order.")]
+    IdempotencyKeyReused,
+    #[error("A shipment is already being created with this idempotency key.")]
+    ShipmentInProgress,
+}
+
+/// body of the response when an error occurs.
+/// The code is stable and can be used by clients to branch on the error.
+#[derive(Serialize, Debug)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::PgError(_)
+            | AppError::DeadPoolError(_)
+            | AppError::DieselError(_)
+            | AppError::Conf
+            | AppError::InvalidConfig(_)
+            | AppError::Storage(_)
+            | AppError::Task(_)
+            | AppError::Pdf(_)
+            | AppError::Xml(_)
+            | AppError::Authentication(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::ReqwestError(e) if e.is_connect() || e.is_timeout() => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            AppError::NoLabel(_) | AppError::ReqwestError(_) | AppError::Rejected { .. } => {
+                StatusCode::BAD_GATEWAY
+            }
+            AppError::Unavailable(_) | AppError::ServiceError(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::OrderNotFound
+            | AppError::TrackingNotFound
+            | AppError::ShipmentNotFound
+            | AppError::UnknownTenant(_)
+            | AppError::TenantMismatch(_)
+            | AppError::UnknownWarehouse(_)
+            | AppError::NoLabelCode
+            | AppError::BadOutput(_)
+            | AppError::InvalidOption(_)
+            | AppError::InvalidReference(_)
+            | AppError::BadSelection(_)
+            | AppError::ReturnImpossible(_)
+            | AppError::BadAddress(_)
+            | AppError::InvalidRelay(_)
+            | AppError::RelayClosed(_)
+            | AppError::InvalidPostCode(_)
+            | AppError::InvalidMode(_)
+            | AppError::WeightOutOfRange(_)
+            | AppError::InvalidParcel(_)
+            | AppError::InvalidBody(_)
+            | AppError::BadIdempotencyKey(_) => StatusCode::BAD_REQUEST,
+            AppError::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::ShipmentInProgress => StatusCode::CONFLICT,
+        }
+    }
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::PgError(_) | AppError::DeadPoolError(_) | AppError::DieselError(_) => {
+                "DATABASE"
+            }
+            AppError::Conf => "CONFIGURATION",
+            AppError::InvalidConfig(_) => "INVALID_CONFIGURATION",
+            AppError::Storage(_) => "STORAGE",
+            AppError::Task(_) => "INTERNAL",
+            AppError::Pdf(_) => "PDF",
+            AppError::Xml(_) => "INVALID_REQUEST_XML",
+            AppError::NoLabel(_) => "NO_LABEL",
+            AppError::ReqwestError(_) => "UPSTREAM_UNREACHABLE",
+            AppError::OrderNotFound => "ORDER_NOT_FOUND",
+            AppError::TrackingNotFound => "TRACKING_NOT_FOUND",
+            AppError::ShipmentNotFound => "SHIPMENT_NOT_FOUND",
+            AppError::UnknownTenant(_) => "UNKNOWN_TENANT",
+            AppError::TenantMismatch(_) => "TENANT_MISMATCH",
+            AppError::UnknownWarehouse(_) => "UNKNOWN_WAREHOUSE",
+            AppError::NoLabelCode => "NO_LABEL_CODE",
+            AppError::BadOutput(_) => "BAD_OUTPUT",
+            AppError::InvalidOption(_) => "INVALID_OPTION",
+            AppError::InvalidReference(_) => "INVALID_REFERENCE",
+            AppError::BadSelection(_) => "BAD_SELECTION",
+            AppError::ReturnImpossible(_) => "RETURN_IMPOSSIBLE",
+            AppError::BadAddress(_) => "BAD_ADDRESS",
+            AppError::InvalidRelay(_) => "INVALID_RELAY",
+            AppError::RelayClosed(_) => "RELAY_CLOSED",
+            AppError::InvalidPostCode(_) => "INVALID_POST_CODE",
+            AppError::InvalidMode(_) => "INVALID_MODE",
+            AppError::WeightOutOfRange(_) => "WEIGHT_OUT_OF_RANGE",
+            AppError::InvalidParcel(_) => "INVALID_PARCEL",
+            AppError::Authentication(_) => "UPSTREAM_AUTHENTICATION",
+            AppError::Rejected { .. } => "UPSTREAM_REJECTED",
+            AppError::Unavailable(_) | AppError::ServiceError(_) => "UPSTREAM_UNAVAILABLE",
+            AppError::InvalidBody(_) => "INVALID_BODY",
+            AppError::BadIdempotencyKey(_) => "BAD_IDEMPOTENCY_KEY",
+            AppError::IdempotencyKeyReused => "IDEMPOTENCY_KEY_REUSED",
+            AppError::ShipmentInProgress => "SHIPMENT_IN_PROGRESS",
+        }
+    }
+    /// Mondial Relay answered with a status refusing the request, so nothing was created.
+    /// Other errors of a creation (timeout, unavailable, unexpected response) may hide created shipments.
+    pub fn is_rejection(&self) -> bool {
+        matches!(
+            self,
+            AppError::Rejected { .. }
+                | AppError::ServiceError(_)
+                | AppError::InvalidRelay(_)
+                | AppError::InvalidPostCode(_)
+                | AppError::BadAddress(_)
+                | AppError::InvalidMode(_)
+                | AppError::WeightOutOfRange(_)
+                | AppError::InvalidParcel(_)
+        )
+    }
+    /// the shipment may have been created by Mondial Relay despite the error:
+    /// no answer, a server error or an answer without the labels.
+    /// Nothing was created when Mondial Relay answered with an error status, even the generic one.
+    pub fn may_have_created(&self) -> bool {
+        matches!(
+            self,
+            AppError::ReqwestError(_)
+                | AppError::Unavailable(_)
+                | AppError::NoLabel(_)
+                | AppError::PgError(_)
+                | AppError::DeadPoolError(_)
+                | AppError::DieselError(_)
+        )
+    }
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        (self.status(), Json(self.body())).into_response()
+    }
+}
+
+/// Map a status of error returned by Mondial Relay to the error given back to the client.
+/// Codes are the ones documented by Mondial Relay for their web services.
+impl From<&Status> for AppError {
+    fn from(status: &Status) -> Self {
+        let message = status.message.clone();
+        match status.code.trim().parse::<u32>() {
+            // brand, password, security key or account not activated
+            Ok(1 | 2 | 8 | 69 | 95 | 97) => AppError::Authentication(message),
+            // collection or delivery relay number and country
+            Ok(11 | 12 | 14 | 15 | 70 | 71) => AppError::InvalidRelay(message),
+            Ok(36) => AppError::InvalidPostCode(message),
+            // street, city, country, phone and email of an address
+            Ok(9 | 30..=35 | 37..=39 | 78 | 79) => AppError::BadAddress(message),
+            // collection or delivery mode
+            Ok(10 | 13 | 27..=29) => AppError::InvalidMode(message),
+            Ok(20) => AppError::WeightOutOfRange(message),
+            // size, number of parcels, multi-parcels forbidden in relay
+            Ok(21 | 22 | 47 | 48) => AppError::InvalidParcel(message),
+            // generic error of the service, the shipment was not created
+            Ok(99) => AppError::ServiceError(message),
+            _ => AppError::Rejected {
+                code: status.code.clone(),
+                message,
+            },
+        }
+    }
 }
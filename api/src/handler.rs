@@ -1,118 +1,969 @@
-use std::{fs::File, io::Write};
-
 use axum::{
     Json,
-    extract::{Path, State},
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Path, Request, State},
+    http::{
+        HeaderMap, StatusCode,
+        header::{ACCEPT, CONTENT_TYPE},
+        request::Parts,
+    },
     response::IntoResponse,
 };
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Utc};
+use diesel::{
+    Connection, ExpressionMethods, NullableExpressionMethods, OptionalExtension,
+    PgExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper,
+    result::{DatabaseErrorKind, Error as DieselError},
+};
+use reqwest::Client;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{collections::BTreeMap, str::FromStr, sync::Arc};
 use tracing::{debug, warn};
 use url::Url;
-use xmltree::Element;
 use xsd_parser::generator::validator::Validate;
 
 use crate::{
     AppState,
+    config::Config,
     db::{
-        model::Shipment,
-        schema::shipments::{self},
+        model::{IdempotencyKey, Shipment, ShipmentEvent, ShipmentRequest},
+        schema::{idempotency_keys, shipment_events, shipment_requests, shipments},
+    },
+    error::{AppError, ErrorBody},
+    reload::{self, Reloaded},
+    request::{
+        self, Address, MAX_SHIPMENTS, OutputOptions, ShipmentCreationRequest,
+        output_options_type::LabelType,
+        parcel_type::Content,
+        product_configuration_type::{CollectionMode, DeliveryMode},
     },
-    error::AppError,
-    request::{Address, ShipmentCreationRequest},
+    response::ShipmentCreationResponse,
+    sheet::{Imposition, merge_labels},
+    webhook::{self, WebhookEvent},
+    webservice,
 };
 #[derive(Deserialize, Serialize, Debug)]
 pub struct NewShipment {
     pub id_order: u32,
-    // should be a variant
-    pub delivery_mode: String,
+    pub delivery_mode: DeliveryMode,
+    // default from the configuration
+    pub collection_mode: Option<CollectionMode>,
     // relay, or Auto if no relay used
     pub delivery_location: Option<String>,
     pub delivery_instructions: Option<String>,
+    // parcels of the shipment, at least one.
+    pub parcels: Vec<NewParcel>,
+    pub recipient_details: Address,
+    // type of label, default from the configuration
+    pub output_type: Option<LabelType>,
+    // format of pdf or printer model, default from the configuration
+    pub output_format: Option<String>,
+    // declared value of the content
+    pub value: Option<NewValue>,
+    // level of insurance, from 0 (none) to 5
+    pub insurance: Option<u8>,
+    // other options of Mondial Relay, by key
+    #[serde(default)]
+    pub options: BTreeMap<String, String>,
+    // references shown on the invoices of Mondial Relay, sent only if enabled in the configuration.
+    // 15 characters at most
+    pub order_no: Option<String>,
+    // 9 characters at most
+    pub customer_no: Option<String>,
+    // brand profile of the configuration, default to the one of the header
+    pub tenant: Option<String>,
+    // warehouse the parcels leave from, default from the configuration
+    pub warehouse: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewValue {
+    // cents
+    pub amount: u32,
+    // ISO 4217 code, like EUR
+    pub currency: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NewParcel {
     // cm
     pub length: u32,
     pub width: u32,
     pub depth: u32,
+    // gr
     pub weight: u32,
-    pub recipient_details: Address,
+    pub content: Option<Content>,
+}
+
+impl NewShipment {
+    /// check the parcels against the limits of Mondial Relay for the delivery mode.
+    pub fn validate_parcels(&self) -> Result<(), AppError> {
+        if self.parcels.is_empty() {
+            return Err(AppError::InvalidParcel(
+                "A shipment needs at least one parcel".to_string(),
+            ));
+        }
+        let max = self.delivery_mode.max_parcel_count();
+        if self.parcels.len() > max {
+            return Err(AppError::InvalidParcel(format!(
+                "Delivery mode {} allows at most {} parcel(s), {} given",
+                self.delivery_mode,
+                max,
+                self.parcels.len()
+            )));
+        }
+        let max_weight = self.delivery_mode.max_weight();
+        for parcel in &self.parcels {
+            if parcel.weight > max_weight {
+                return Err(AppError::WeightOutOfRange(format!(
+                    "Delivery mode {} allows parcels up to {} gr, {} given",
+                    self.delivery_mode, max_weight, parcel.weight
+                )));
+            }
+            if let Some(content) = &parcel.content {
+                content.validate().map_err(AppError::InvalidParcel)?;
+            }
+        }
+        Ok(())
+    }
+    /// check the location is given only for delivery modes using one.
+    pub fn validate_location(&self) -> Result<(), AppError> {
+        let mode = self.delivery_mode;
+        match &self.delivery_location {
+            None if mode.needs_location() => Err(AppError::InvalidRelay(format!(
+                "Delivery mode {mode} needs a location"
+            ))),
+            Some(location) if !mode.needs_location() => Err(AppError::InvalidRelay(format!(
+                "Delivery mode {mode} delivers to the address, location {location} given"
+            ))),
+            Some(location) if !mode.allows_auto() && is_auto(location) => {
+                Err(AppError::InvalidRelay(format!(
+                    "Delivery mode {mode} needs the location chosen by the recipient"
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// location letting Mondial Relay choose the relay: Auto, or country and Auto like FR-Auto.
+fn is_auto(location: &str) -> bool {
+    location
+        .rsplit('-')
+        .next()
+        .is_some_and(|l| l.eq_ignore_ascii_case("auto"))
+}
+
+/// header given by the client to make the creation of a shipment idempotent.
+/// A request repeated with the same key returns the shipment created by the first one.
+pub const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+
+/// header selecting the brand profile of the configuration, the default one without it.
+pub const TENANT_HEADER: &str = "X-Mondialrelay-Tenant";
+
+/// tenant selected by the header, shipments and labels of other tenants are not seen.
+pub struct Tenant(pub Option<String>);
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for Tenant {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        let Some(name) = parts.headers.get(TENANT_HEADER) else {
+            return Ok(Tenant(None));
+        };
+        let name = name
+            .to_str()
+            .map_err(|e| AppError::UnknownTenant(e.to_string()))?;
+        if !state.config().tenants.contains_key(name) {
+            return Err(AppError::UnknownTenant(name.to_string()));
+        }
+        Ok(Tenant(Some(name.to_string())))
+    }
+}
+
+impl Tenant {
+    /// tenant of a shipment, given by the header or the body.
+    /// Both must be the same if both are given, the body can not pick another brand account.
+    fn of_shipment(&self, body: Option<String>) -> Result<Option<String>, AppError> {
+        match (&self.0, body) {
+            (Some(header), Some(body)) if *header != body => Err(AppError::TenantMismatch(
+                format!("{body} in the shipment, {header} in the header"),
+            )),
+            (header, body) => Ok(body.or(header.clone())),
+        }
+    }
+}
+
+/// json body of a request, rejected with an error of the API instead of the plain text of axum:
+/// malformed json, unknown delivery mode, missing field...
+pub struct JsonBody<T>(pub T);
+
+#[axum::async_trait]
+impl<T, S> FromRequest<S> for JsonBody<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, AppError> {
+        let Json(data) = Json::<T>::from_request(req, state)
+            .await
+            .map_err(|rejection| AppError::InvalidBody(rejection.body_text()))?;
+        Ok(JsonBody(data))
+    }
 }
 
 // create a shipment
 #[axum::debug_handler]
 pub async fn shipment(
     State(state): State<AppState>,
-    Json(data): Json<NewShipment>,
+    tenant: Tenant,
+    headers: HeaderMap,
+    JsonBody(mut data): JsonBody<NewShipment>,
 ) -> Result<impl IntoResponse, AppError> {
     debug!("Serving request for new shipment...");
+    data.tenant = tenant.of_shipment(data.tenant)?;
+    let key = headers
+        .get(IDEMPOTENCY_KEY)
+        .map(|key| key.to_str().map(str::to_string))
+        .transpose()
+        .map_err(|e| AppError::BadIdempotencyKey(e.to_string()))?;
+    // the keys of the tenants are kept apart.
+    let key_tenant = data.tenant.clone().unwrap_or_default();
+    if let Some(key) = &key
+        && let Some(tracking) =
+            reserve_idempotency_key(&state, key_tenant.clone(), key.clone(), data.id_order).await?
+    {
+        debug!("Shipment already created for this key, returning its tracking id.");
+        return Ok(tracking);
+    }
+    // the creation goes on if the client disconnects, so the key is always settled.
+    let task_state = state.clone();
+    let tracking = tokio::spawn(async move {
+        let state = task_state;
+        match create_shipment(&state, data, key.clone()).await {
+            Ok(tracking) => Ok(tracking),
+            Err(e) => {
+                // the shipment was not created, the client can retry with the same key.
+                if let Some(key) = key
+                    && !e.may_have_created()
+                {
+                    release_idempotency_key(&state, key_tenant, key).await?;
+                }
+                Err(e)
+            }
+        }
+    })
+    .await??;
+    debug!("Returning tracking id.");
+    Ok(tracking)
+}
+
+/// result of the creation of one shipment of a batch.
+#[derive(Serialize, Debug)]
+pub struct BatchResult {
+    pub id_order: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tracking_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorBody>,
+}
+
+// create many shipments, with as few requests to Mondial Relay as possible.
+// Shipments of the same tenant with the same label output are sent together.
+#[axum::debug_handler]
+pub async fn shipments(
+    State(state): State<AppState>,
+    tenant: Tenant,
+    JsonBody(data): JsonBody<Vec<NewShipment>>,
+) -> Result<impl IntoResponse, AppError> {
+    debug!("Serving request for {} new shipments...", data.len());
+    let mut results = data
+        .iter()
+        .map(|shipment| BatchResult {
+            id_order: shipment.id_order,
+            tracking_number: None,
+            error: None,
+        })
+        .collect::<Vec<_>>();
+    // shipments grouped by tenant and label output, with their position in the batch.
+    type Group = (Option<String>, OutputOptions);
+    let mut groups: Vec<(Group, Vec<(usize, Prepared)>)> = vec![];
+    for (i, mut shipment) in data.into_iter().enumerate() {
+        let prepared = match tenant.of_shipment(shipment.tenant.take()) {
+            Ok(name) => {
+                shipment.tenant = name;
+                prepare(&state, shipment, None).await
+            }
+            Err(e) => Err(e),
+        };
+        match prepared {
+            Ok((output, prepared)) => {
+                let key = (prepared.tenant.clone(), output);
+                match groups.iter_mut().find(|(k, _)| *k == key) {
+                    Some((_, group)) => group.push((i, prepared)),
+                    None => groups.push((key, vec![(i, prepared)])),
+                }
+            }
+            Err(e) => results[i].error = Some(e.body()),
+        }
+    }
+    for ((tenant, output), mut group) in groups {
+        while !group.is_empty() {
+            let chunk = group
+                .drain(..group.len().min(MAX_SHIPMENTS))
+                .collect::<Vec<_>>();
+            let (positions, chunk): (Vec<_>, Vec<_>) = chunk.into_iter().unzip();
+            match create_shipments(&state, tenant.as_deref(), output.clone(), chunk.clone()).await {
+                Ok(trackings) => {
+                    for (i, tracking) in positions.into_iter().zip(trackings) {
+                        results[i].tracking_number = Some(tracking);
+                    }
+                }
+                // Mondial Relay doesn't tell which shipment is wrong,
+                // so they are sent again one by one to find the failing ones.
+                Err(e) if chunk.len() > 1 && e.is_rejection() => {
+                    warn!("Batch of {} shipments failed: {e}", chunk.len());
+                    for (i, prepared) in positions.into_iter().zip(chunk) {
+                        match create_shipments(
+                            &state,
+                            tenant.as_deref(),
+                            output.clone(),
+                            vec![prepared],
+                        )
+                        .await
+                        {
+                            Ok(mut tracking) => results[i].tracking_number = tracking.pop(),
+                            Err(e) => results[i].error = Some(e.body()),
+                        }
+                    }
+                }
+                // the shipments may have been created anyway, sending them again could make duplicates.
+                Err(e) => {
+                    for i in positions {
+                        results[i].error = Some(e.body());
+                    }
+                }
+            }
+        }
+    }
+    debug!("Returning results of the batch.");
+    Ok(Json(results))
+}
+
+/// shipment validated and ready to be sent to Mondial Relay.
+#[derive(Clone)]
+struct Prepared {
+    order_id: u32,
+    label_type: LabelType,
+    shipment: request::Shipment,
+    idempotency_key: Option<String>,
+    // original shipment if this one is a return
+    return_of: Option<i32>,
+    tenant: Option<String>,
+    warehouse: Option<String>,
+}
+
+/// validate the data of a new shipment, returns the label output asked and the shipment to send.
+async fn prepare(
+    state: &AppState,
+    data: NewShipment,
+    idempotency_key: Option<String>,
+) -> Result<(OutputOptions, Prepared), AppError> {
     // validate NewShipment data,
     data.recipient_details
         .validate()
         .map_err(AppError::BadAddress)?;
-    // save order id
+    data.validate_location()?;
+    data.validate_parcels()?;
+    // the same configuration for the whole shipment, even if it is reloaded meanwhile.
+    let config = state.config_of(data.tenant.as_deref())?;
+    let warehouse = data.warehouse.clone().or(config.default_warehouse.clone());
+    let config = from_warehouse(config, warehouse.as_deref())?;
+    if config.web_service.check_relay {
+        check_relay(state, &config, &data).await?;
+    }
+    let label_type = data.output_type.unwrap_or(config.output_type);
+    let output = OutputOptions::new(
+        label_type,
+        data.output_format.clone().unwrap_or(config.format.clone()),
+    );
+    output.validate().map_err(AppError::BadOutput)?;
     let order_id = data.id_order;
+    let tenant = data.tenant.clone();
+    let shipment = request::Shipment::new(&config, data)?;
+    // the addresses are checked first, to report them as such and not as invalid options.
+    shipment.sender.validate().map_err(AppError::BadAddress)?;
+    shipment
+        .recipient
+        .validate()
+        .map_err(AppError::BadAddress)?;
+    shipment.validate().map_err(AppError::InvalidOption)?;
+    Ok((
+        output,
+        Prepared {
+            order_id,
+            label_type,
+            shipment,
+            idempotency_key,
+            return_of: None,
+            tenant,
+            warehouse,
+        },
+    ))
+}
+
+/// configuration sending from the warehouse, from the address of the sender without one.
+fn from_warehouse(config: Arc<Config>, name: Option<&str>) -> Result<Arc<Config>, AppError> {
+    let Some(name) = name else {
+        return Ok(config);
+    };
+    config
+        .warehouse(name)
+        .map(Arc::new)
+        .ok_or(AppError::UnknownWarehouse(name.to_string()))
+}
+
+/// ask Mondial Relay if the relay of the delivery exists, allows the delivery mode and is open today.
+/// The relays are still found by the client, it is only a validation.
+async fn check_relay(
+    state: &AppState,
+    config: &Config,
+    data: &NewShipment,
+) -> Result<(), AppError> {
+    let Some(location) = &data.delivery_location else {
+        return Ok(());
+    };
+    if !data.delivery_mode.needs_location() || is_auto(location) {
+        return Ok(());
+    }
+    // doc show FR-00001 but schema show FR00001
+    let (country, number) = location
+        .split_once('-')
+        .or(location.split_at_checked(2))
+        .ok_or(AppError::InvalidRelay(format!(
+            "Location {location} should be like FR-12345"
+        )))?;
+    let relay = webservice::find_relay(
+        &state.client,
+        &config.web_service,
+        country,
+        number,
+        data.delivery_mode.as_str(),
+    )
+    .await?
+    .ok_or(AppError::InvalidRelay(format!(
+        "Relay {location} does not exist or does not allow the delivery mode {}",
+        data.delivery_mode
+    )))?;
+    if relay.is_closed_on(Utc::now().date_naive()) {
+        return Err(AppError::RelayClosed(location.clone()));
+    }
+    Ok(())
+}
+
+/// create the shipment on Mondial Relay and save it, returns the tracking id.
+async fn create_shipment(
+    state: &AppState,
+    data: NewShipment,
+    idempotency_key: Option<String>,
+) -> Result<String, AppError> {
+    let (output, prepared) = prepare(state, data, idempotency_key).await?;
+    let tenant = prepared.tenant.clone();
+    let mut trackings = create_shipments(state, tenant.as_deref(), output, vec![prepared]).await?;
+    trackings
+        .pop()
+        .ok_or(AppError::NoLabel("No Shipment".to_string()))
+}
+
+/// create the shipments with one request on Mondial Relay and save them,
+/// returns the tracking ids in the same order.
+/// If Mondial Relay refuses one shipment, none is created.
+async fn create_shipments(
+    state: &AppState,
+    tenant: Option<&str>,
+    output: OutputOptions,
+    prepared: Vec<Prepared>,
+) -> Result<Vec<String>, AppError> {
+    let config = state.config_of(tenant)?;
     // construct the request
-    let shipment = ShipmentCreationRequest::new(state.config.clone(), data)?;
+    let request = ShipmentCreationRequest::new(
+        &config,
+        output,
+        prepared.iter().map(|p| p.shipment.clone()).collect(),
+    )?;
     // validate shipment request, return simple error to client, debugged error to server
-    shipment.validate().map_err(AppError::Xml)?;
+    request.validate().map_err(AppError::Xml)?;
 
-    // convert to xml
-    let xml = yaserde::ser::to_string_with_config(&shipment, &yaserde::ser::Config {
-        perform_indent: true,
+    // keep a trace of the exchange with Mondial Relay, without the credentials.
+    // Every order of the request gets its own copy.
+    let mut audit = ShipmentRequest {
+        request_xml: to_xml(&request.redacted()),
         ..Default::default()
-    })
-    .expect("invalid UTF-8");
-    // send request
-    let url = if state.config.test {
-        // sandbox doesn't work currently
-        // "https://connect-api-sandbox.mondialrelay.com/api/shipment"
-        "https://connect-api.mondialrelay.com/api/shipment"
+    };
+    let result = send_shipment(&state.client, &config, &request, &mut audit).await;
+    if let Err(e) = &result {
+        audit.error = Some(e.to_string());
+    }
+    // written whatever happens next, the shipments are linked once saved.
+    let audits = prepared
+        .iter()
+        .map(|p| ShipmentRequest {
+            order_id: p.order_id as i32,
+            ..audit.clone()
+        })
+        .collect::<Vec<_>>();
+    let conn = state.pool.get().await?;
+    let audit_ids = conn
+        .interact(move |conn| {
+            diesel::insert_into(shipment_requests::table)
+                .values(audits)
+                .returning(shipment_requests::id)
+                .get_results::<i32>(conn)
+        })
+        .await?
+        .unwrap_or_else(|e| {
+            // the shipments created must still be saved.
+            warn!("Could not keep the request to Mondial Relay: {e}");
+            vec![]
+        });
+    let labels = result?;
+    // the keys give back the shipments from now on, even if they can not be saved.
+    let created = prepared
+        .iter()
+        .zip(&labels)
+        .filter_map(|(p, (tracking, _))| Some((p.idempotency_key.clone()?, tracking.clone())))
+        .collect::<Vec<_>>();
+    // the keys of the default profile have an empty tenant.
+    let key_tenant = tenant.unwrap_or_default().to_string();
+    if !created.is_empty() {
+        let conn = state.pool.get().await?;
+        let key_tenant = key_tenant.clone();
+        conn.interact(move |conn| {
+            for (key, tracking) in created {
+                diesel::update(idempotency_keys::table.find((&key_tenant, key)))
+                    .set(idempotency_keys::tracking_number.eq(tracking))
+                    .execute(conn)?;
+            }
+            Ok::<(), diesel::result::Error>(())
+        })
+        .await??;
+    }
+    // save id of order and label in to db
+    let mut shipments = vec![];
+    for (p, (tracking, label)) in prepared.iter().zip(&labels) {
+        let mut shipment = Shipment {
+            order_id: p.order_id as i32,
+            tracking_number: tracking.clone(),
+            label_type: p.label_type.as_str().to_string(),
+            is_return: p.return_of.is_some(),
+            return_of: p.return_of,
+            tenant: tenant.map(str::to_string),
+            warehouse: p.warehouse.clone(),
+            ..Default::default()
+        };
+        match p.label_type {
+            LabelType::PdfUrl => {
+                shipment.label_url = Some(
+                    Url::parse(label)
+                        .map_err(|e| AppError::NoLabel(e.to_string()))?
+                        .to_string(),
+                )
+            }
+            LabelType::ZplCode | LabelType::IplCode => shipment.label_code = Some(label.clone()),
+        }
+        shipments.push(shipment);
+    }
+    let label_urls = shipments
+        .iter()
+        .map(|shipment| shipment.label_url.clone())
+        .collect::<Vec<_>>();
+    let keys = prepared
+        .into_iter()
+        .map(|p| p.idempotency_key)
+        .collect::<Vec<_>>();
+    let conn = state.pool.get().await?;
+    // wait the writing to finish, so client is sure the shipments are saved.
+    let saved = conn
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let mut saved = vec![];
+                for (i, (shipment, key)) in shipments.into_iter().zip(keys).enumerate() {
+                    let shipment = diesel::insert_into(shipments::table)
+                        .values(shipment)
+                        .returning(Shipment::as_returning())
+                        .get_result(conn)?;
+                    let id = shipment.id;
+                    if let Some(audit) = audit_ids.get(i) {
+                        diesel::update(shipment_requests::table.find(audit))
+                            .set(shipment_requests::shipment_id.eq(id))
+                            .execute(conn)?;
+                    }
+                    if let Some(key) = key {
+                        diesel::update(idempotency_keys::table.find((&key_tenant, key)))
+                            .set(idempotency_keys::shipment_id.eq(id))
+                            .execute(conn)?;
+                    }
+                    saved.push(shipment);
+                }
+                Ok::<Vec<Shipment>, diesel::result::Error>(saved)
+            })
+        })
+        .await??;
+    for shipment in &saved {
+        webhook::notify(state, WebhookEvent::ShipmentCreated, shipment).await;
+        // the code for printers is given in the response of Mondial Relay.
+        if shipment.label_code.is_some() {
+            webhook::notify(state, WebhookEvent::LabelAvailable, shipment).await;
+        }
+    }
+    // keep a copy of the pdfs, without making the client wait for them.
+    for (shipment, label_url) in saved.iter().zip(label_urls) {
+        let id = shipment.id;
+        if let Some(label_url) = label_url {
+            let state = state.clone();
+            tokio::spawn(async move {
+                if let Err(e) = label_pdf(&state, id, &label_url).await {
+                    warn!("Could not save the label of shipment n°{id}: {e}");
+                }
+            });
+        }
+    }
+    Ok(labels.into_iter().map(|(tracking, _)| tracking).collect())
+}
+
+/// options of a return label.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct NewReturn {
+    // Point Relais where the recipient drops the parcels off,
+    // default to the Point Relais of the delivery.
+    pub collection_location: Option<String>,
+}
+
+// create a label for the recipient of the last shipment of an order, to send the parcels back.
+// Returns the tracking id of the return, the one already created if the order has one.
+#[axum::debug_handler]
+pub async fn return_shipment(
+    State(state): State<AppState>,
+    Tenant(tenant): Tenant,
+    Path(id_order): Path<u32>,
+    body: Bytes,
+) -> Result<impl IntoResponse, AppError> {
+    debug!("Serving request for return of order n°{}...", id_order);
+    // the options can be left out, an empty body takes the default ones.
+    let data = if body.is_empty() {
+        NewReturn::default()
     } else {
-        "https://connect-api.mondialrelay.com/api/shipment"
+        serde_json::from_slice::<NewReturn>(&body)
+            .map_err(|e| AppError::InvalidBody(e.to_string()))?
+    };
+    let conn = state.pool.get().await?;
+    let of_tenant = tenant.clone();
+    let returned = conn
+        .interact(move |conn| {
+            let Some((original_id, tracking, warehouse)) = shipments::table
+                .filter(shipments::order_id.eq(id_order as i32))
+                .filter(shipments::tenant.is_not_distinct_from(of_tenant))
+                .filter(shipments::is_return.eq(false))
+                .order(shipments::created_at.desc())
+                .select((
+                    shipments::id,
+                    shipments::tracking_number,
+                    shipments::warehouse,
+                ))
+                .first::<(i32, String, Option<String>)>(conn)
+                .optional()?
+            else {
+                return Ok(None);
+            };
+            // a retried request must not send the parcels back twice.
+            if let Some(existing) = shipments::table
+                .filter(shipments::return_of.eq(original_id))
+                .filter(shipments::is_return.eq(true))
+                .select(shipments::tracking_number)
+                .first::<String>(conn)
+                .optional()?
+            {
+                return Ok(Some(Err(existing)));
+            }
+            let audit = shipment_requests::table
+                .filter(shipment_requests::shipment_id.eq(original_id))
+                .select(ShipmentRequest::as_select())
+                .first(conn)
+                .optional()?;
+            Ok::<_, diesel::result::Error>(Some(Ok((original_id, tracking, warehouse, audit))))
+        })
+        .await??
+        .ok_or(AppError::OrderNotFound)?;
+    let (original_id, tracking, warehouse, audit) = match returned {
+        Ok(original) => original,
+        Err(existing) => {
+            debug!("Order already returned, returning tracking id of the return.");
+            return Ok(existing);
+        }
+    };
+    let audit = audit.ok_or(AppError::ReturnImpossible(
+        "The request of the shipment was not kept".to_string(),
+    ))?;
+    let original = sent_shipment(&tracking, &audit)?;
+    // the return is dropped off at a Point Relais, which takes a single parcel.
+    if original.parcels.parcel.len() > 1 {
+        return Err(AppError::InvalidParcel(format!(
+            "A return is collected at a Point Relais, which takes one parcel, the shipment has {}",
+            original.parcels.parcel.len()
+        )));
+    }
+    let location = data
+        .collection_location
+        .or(original
+            .delivery_mode
+            .location
+            .clone()
+            .filter(|location| !is_auto(location)))
+        .ok_or(AppError::ReturnImpossible(
+            "The shipment was not delivered to a Point Relais, give the collection location"
+                .to_string(),
+        ))?;
+    // the parcels go back to the warehouse they left from.
+    let config = from_warehouse(state.config_of(tenant.as_deref())?, warehouse.as_deref())?;
+    let label_type = config.output_type;
+    let prepared = Prepared {
+        order_id: id_order,
+        label_type,
+        shipment: original.return_of(&config, location),
+        idempotency_key: None,
+        return_of: Some(original_id),
+        tenant: tenant.clone(),
+        warehouse,
+    };
+    let output = OutputOptions::new(label_type, config.format.clone());
+    let tracking = match create_shipments(&state, tenant.as_deref(), output, vec![prepared]).await {
+        // a concurrent request saved the return first, the shipment keeps only this one.
+        Err(AppError::DieselError(DieselError::DatabaseError(
+            DatabaseErrorKind::UniqueViolation,
+            info,
+        ))) if info.constraint_name() == Some(RETURN_INDEX) => {
+            let conn = state.pool.get().await?;
+            conn.interact(move |conn| {
+                shipments::table
+                    .filter(shipments::return_of.eq(original_id))
+                    .filter(shipments::is_return.eq(true))
+                    .select(shipments::tracking_number)
+                    .first::<String>(conn)
+            })
+            .await??
+        }
+        result => result?
+            .pop()
+            .ok_or(AppError::NoLabel("No Shipment".to_string()))?,
     };
-    // debug response from test
-    let mut buffer = File::create("request_generated.xml").unwrap();
-    buffer.write_all(&xml.clone().into_bytes()).unwrap();
-    let resp_xml = state
+    debug!("Returning tracking id of the return.");
+    Ok(tracking)
+}
+
+/// unique index keeping one return for a shipment.
+const RETURN_INDEX: &str = "shipments_return_of_idx";
+
+/// shipment with this tracking number, as sent to Mondial Relay.
+/// A request can hold many shipments, given back in the same order in the response.
+fn sent_shipment(tracking: &str, audit: &ShipmentRequest) -> Result<request::Shipment, AppError> {
+    let request: ShipmentCreationRequest =
+        yaserde::de::from_str(&audit.request_xml).map_err(AppError::Xml)?;
+    let response: ShipmentCreationResponse =
+        yaserde::de::from_str(audit.response_xml.as_deref().unwrap_or_default())
+            .map_err(AppError::NoLabel)?;
+    response
+        .shipments()
+        .iter()
+        .position(|shipment| shipment.shipment_number == tracking)
+        .and_then(|position| request.shipments_list.shipment.into_iter().nth(position))
+        .ok_or(AppError::ReturnImpossible(
+            "The shipment is missing from the request kept".to_string(),
+        ))
+}
+
+/// returns the pdf of the label of a shipment.
+/// It is downloaded from Mondial Relay and saved in the storage if it isn't already.
+pub async fn label_pdf(state: &AppState, id: i32, label_url: &str) -> Result<Vec<u8>, AppError> {
+    let name = format!("{id}.pdf");
+    let storage = state.storage.clone();
+    let saved_name = name.clone();
+    if let Some(pdf) = tokio::task::spawn_blocking(move || storage.load(&saved_name)).await?? {
+        return Ok(pdf);
+    }
+    debug!("Downloading label of shipment n°{id}");
+    let pdf = state
         .client
-        .post(url)
-        .body(xml)
+        .get(label_url)
+        .header(ACCEPT, "application/pdf")
         .send()
         .await?
+        .error_for_status()?
         .bytes()
-        .await?;
+        .await?
+        .to_vec();
+    let storage = state.storage.clone();
+    let content = pdf.clone();
+    tokio::task::spawn_blocking(move || storage.save(&name, &content)).await??;
+    let conn = state.pool.get().await?;
+    let shipment = conn
+        .interact(move |conn| {
+            shipments::table
+                .find(id)
+                .select(Shipment::as_select())
+                .first(conn)
+        })
+        .await??;
+    webhook::notify(state, WebhookEvent::LabelAvailable, &shipment).await;
+    Ok(pdf)
+}
+
+/// send the request to Mondial Relay, returns the shipment number and label output of every shipment.
+/// The response is kept in the audit.
+async fn send_shipment(
+    client: &Client,
+    config: &Config,
+    shipment: &ShipmentCreationRequest,
+    audit: &mut ShipmentRequest,
+) -> Result<Vec<(String, String)>, AppError> {
+    let url = config.shipment_url().map_err(|_| AppError::Conf)?;
+    let resp = client.post(url).body(to_xml(shipment)).send().await?;
+    let status = resp.status();
+    audit.http_status = Some(status.as_u16() as i32);
+    let resp_xml = resp.text().await?;
+    audit.response_xml = Some(resp_xml.clone());
+    if status.is_server_error() {
+        return Err(AppError::Unavailable(status.to_string()));
+    }
+    // refused before reaching the service, with an html or empty body.
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        return Err(AppError::Authentication(status.to_string()));
+    }
+    let response = yaserde::de::from_str::<ShipmentCreationResponse>(&resp_xml);
+    if status.is_client_error()
+        && !response
+            .as_ref()
+            .is_ok_and(|response| response.errors().next().is_some())
+    {
+        return Err(AppError::Rejected {
+            code: status.as_str().to_string(),
+            message: format!("HTTP status {status} without a status of Mondial Relay"),
+        });
+    }
+    let response = response.map_err(AppError::NoLabel)?;
+    find_labels(&response, shipment.shipments_list.shipment.len())
+}
+
+fn to_xml(shipment: &ShipmentCreationRequest) -> String {
+    yaserde::ser::to_string_with_config(
+        shipment,
+        &yaserde::ser::Config {
+            perform_indent: true,
+            ..Default::default()
+        },
+    )
+    .expect("invalid UTF-8")
+}
 
-    let label_url = find_label(&resp_xml)?.to_string();
-    // save id of order and label url in to db
-    // tracking id is included in url of label
+/// time after which a key reserved by a creation that never ended can be reserved again.
+const RESERVATION_TIMEOUT: i64 = 900;
+
+/// reserve the idempotency key of the tenant for the order, the tenant is empty for the default profile.
+/// Returns the tracking id if a shipment was already created with this key.
+async fn reserve_idempotency_key(
+    state: &AppState,
+    tenant: String,
+    key: String,
+    id_order: u32,
+) -> Result<Option<String>, AppError> {
     let conn = state.pool.get().await?;
-    let shipment = Shipment {
-        order_id: order_id as i32,
-        label_url: label_url.clone(),
-        ..Default::default()
-    };
-    // wait the writing to finish, so client is sure the shipment is saved.
     conn.interact(move |conn| {
-        diesel::insert_into(shipments::table)
-            .values(shipment)
-            .execute(conn)
+        let reserved = diesel::insert_into(idempotency_keys::table)
+            .values(IdempotencyKey {
+                key: key.clone(),
+                order_id: id_order as i32,
+                tenant: tenant.clone(),
+                ..Default::default()
+            })
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        if reserved == 1 {
+            return Ok(None);
+        }
+        let (order, shipment, tracking) = idempotency_keys::table
+            .find((&tenant, &key))
+            .select((
+                idempotency_keys::order_id,
+                idempotency_keys::shipment_id,
+                idempotency_keys::tracking_number,
+            ))
+            .first::<(i32, Option<i32>, Option<String>)>(conn)?;
+        if order != id_order as i32 {
+            return Err(AppError::IdempotencyKeyReused);
+        }
+        if tracking.is_some() {
+            return Ok(tracking);
+        }
+        if let Some(shipment) = shipment {
+            return Ok(Some(
+                shipments::table
+                    .find(shipment)
+                    .select(shipments::tracking_number)
+                    .first(conn)?,
+            ));
+        }
+        // the first request is still waiting for Mondial Relay, unless it was interrupted long ago.
+        let now = Utc::now();
+        let expired = diesel::update(
+            idempotency_keys::table
+                .find((&tenant, &key))
+                .filter(idempotency_keys::shipment_id.is_null())
+                .filter(idempotency_keys::tracking_number.is_null())
+                .filter(
+                    idempotency_keys::created_at
+                        .lt(now - chrono::Duration::seconds(RESERVATION_TIMEOUT)),
+                ),
+        )
+        .set(idempotency_keys::created_at.eq(now))
+        .execute(conn)?;
+        if expired == 0 {
+            return Err(AppError::ShipmentInProgress);
+        }
+        Ok(None)
     })
-    .await??;
-    let tracking = Url::parse(&label_url)
-        .expect("label url given from mondialrelay should have a correct syntax")
-        .query_pairs()
-        .find(|(c, _)| c == "expedition")
-        .expect("there should be always a expedition query")
-        .1
-        .to_string();
+    .await?
+}
 
-    debug!("Returning tracking id.");
-    Ok(tracking)
+/// free the idempotency key after a failed creation.
+/// A key is never freed once Mondial Relay created the shipment.
+async fn release_idempotency_key(
+    state: &AppState,
+    tenant: String,
+    key: String,
+) -> Result<(), AppError> {
+    let conn = state.pool.get().await?;
+    conn.interact(move |conn| {
+        diesel::delete(
+            idempotency_keys::table
+                .find((tenant, key))
+                .filter(idempotency_keys::shipment_id.is_null())
+                .filter(idempotency_keys::tracking_number.is_null()),
+        )
+        .execute(conn)
+    })
+    .await??;
+    Ok(())
 }
+
 /// returns label url for an order.
 /// There can be multiple label for an order if multiple shipments has been created for one order.
+/// Labels for printers have no url, they are given by label_code.
 #[axum::debug_handler]
 pub async fn label(
     State(state): State<AppState>,
+    Tenant(name): Tenant,
     Path(id_order): Path<u32>,
 ) -> Result<impl IntoResponse, AppError> {
     use crate::db::schema::shipments::dsl::*; // get url from order_id in db
@@ -124,7 +975,11 @@ pub async fn label(
             Ok::<Vec<String>, AppError>(
                 shipments
                     .filter(order_id.eq(id_order as i32))
-                    .select(label_url)
+                    .filter(tenant.is_not_distinct_from(name))
+                    // the return labels are given by the tracking of the return.
+                    .filter(is_return.eq(false))
+                    .filter(label_url.is_not_null())
+                    .select(label_url.assume_not_null())
                     .load(conn)?,
             )
         })
@@ -132,7 +987,7 @@ pub async fn label(
     // return url
     if labels.is_empty() {
         warn!(
-            "order n°{} label was requested but order does not exist !",
+            "order n°{} label was requested but order does not exist or has only printer labels !",
             id_order
         );
         return Err(AppError::OrderNotFound);
@@ -141,24 +996,250 @@ pub async fn label(
     Ok(Json(labels))
 }
 
-fn find_label(resp_xml: &[u8]) -> Result<Url, AppError> {
-    let element = Element::parse(resp_xml).map_err(|e| AppError::NoLabel(e.to_string()))?;
-    debug!("{:?}", element);
-    Url::parse(
-        element
-            .get_child("ShipmentsList")
-            .ok_or(AppError::NoLabel("No ShipmentsList".to_string()))?
-            .get_child("Shipment")
-            .ok_or(AppError::NoLabel("No Shipment".to_string()))?
-            .get_child("LabelList")
-            .ok_or(AppError::NoLabel("No LabelList".to_string()))?
-            .get_child("Label")
-            .ok_or(AppError::NoLabel("No Label".to_string()))?
-            .get_child("Output")
-            .ok_or(AppError::NoLabel("No Output".to_string()))?
-            .get_text()
-            .ok_or(AppError::NoLabel("No text found".to_string()))?
-            .as_ref(),
-    )
-    .map_err(|e| AppError::NoLabel(e.to_string()))
+/// returns the printer code of the labels of an order, to send as is to the thermal printer.
+/// Codes of the labels of the same type than the last one are concatenated.
+#[axum::debug_handler]
+pub async fn label_code(
+    State(state): State<AppState>,
+    Tenant(name): Tenant,
+    Path(id_order): Path<u32>,
+) -> Result<impl IntoResponse, AppError> {
+    use crate::db::schema::shipments::dsl::*;
+
+    debug!("handling request \"Label code\" for order n°{}", id_order);
+    let conn = state.pool.get().await?;
+    let labels = conn
+        .interact(move |conn| {
+            shipments
+                .filter(order_id.eq(id_order as i32))
+                .filter(tenant.is_not_distinct_from(name))
+                .filter(is_return.eq(false))
+                .filter(label_code.is_not_null())
+                .order(created_at.desc())
+                .select((label_type, label_code.assume_not_null()))
+                .load::<(String, String)>(conn)
+        })
+        .await??;
+    let Some((last_type, _)) = labels.first() else {
+        warn!(
+            "order n°{} label code was requested but order has none !",
+            id_order
+        );
+        return Err(AppError::NoLabelCode);
+    };
+    let content_type = LabelType::from_str(last_type)
+        .map_err(AppError::NoLabel)?
+        .content_type();
+    let codes = labels
+        .iter()
+        .rev()
+        .filter(|(t, _)| t == last_type)
+        .map(|(_, code)| code.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    debug!("Returning label code(s) for order n°{}", id_order);
+    Ok(([(CONTENT_TYPE, content_type)], codes))
+}
+
+/// returns the pdf of the last label of an order, from the copy kept by the server.
+#[axum::debug_handler]
+pub async fn label_file(
+    State(state): State<AppState>,
+    Tenant(name): Tenant,
+    Path(id_order): Path<u32>,
+) -> Result<impl IntoResponse, AppError> {
+    use crate::db::schema::shipments::dsl::*;
+
+    debug!("handling request \"Label pdf\" for order n°{}", id_order);
+    let conn = state.pool.get().await?;
+    let (shipment_id, url) = conn
+        .interact(move |conn| {
+            shipments
+                .filter(order_id.eq(id_order as i32))
+                .filter(tenant.is_not_distinct_from(name))
+                .filter(is_return.eq(false))
+                .filter(label_url.is_not_null())
+                .order(created_at.desc())
+                .select((id, label_url.assume_not_null()))
+                .first::<(i32, String)>(conn)
+                .optional()
+        })
+        .await??
+        .ok_or(AppError::OrderNotFound)?;
+    let pdf = label_pdf(&state, shipment_id, &url).await?;
+    debug!("Returning label pdf for order n°{}", id_order);
+    Ok(([(CONTENT_TYPE, LabelType::PdfUrl.content_type())], pdf))
+}
+
+/// selection of labels to print at once.
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct LabelSheet {
+    // labels of these orders
+    #[serde(default)]
+    pub id_orders: Vec<u32>,
+    // labels created in this range
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    // labels of the parcels leaving from this warehouse
+    pub warehouse: Option<String>,
+    #[serde(default)]
+    pub imposition: Imposition,
+}
+
+/// returns one pdf with the labels of many orders, ordered by date of creation.
+#[axum::debug_handler]
+pub async fn label_sheet(
+    State(state): State<AppState>,
+    Tenant(name): Tenant,
+    JsonBody(selection): JsonBody<LabelSheet>,
+) -> Result<impl IntoResponse, AppError> {
+    use crate::db::schema::shipments::dsl::*;
+
+    debug!("handling request \"Label sheet\" for {:?}", selection);
+    if selection.id_orders.is_empty() && selection.from.is_none() {
+        return Err(AppError::BadSelection(
+            "Give the orders or the start of the range of the labels".to_string(),
+        ));
+    }
+    let conn = state.pool.get().await?;
+    let ids = selection
+        .id_orders
+        .iter()
+        .map(|o| *o as i32)
+        .collect::<Vec<_>>();
+    let labels = conn
+        .interact(move |conn| {
+            let mut query = shipments
+                .filter(tenant.is_not_distinct_from(name))
+                .filter(is_return.eq(false))
+                .filter(label_url.is_not_null())
+                .order(created_at.asc())
+                .select((id, label_url.assume_not_null()))
+                .into_boxed();
+            if !ids.is_empty() {
+                query = query.filter(order_id.eq_any(ids));
+            }
+            if let Some(from) = selection.from {
+                query = query.filter(created_at.ge(from));
+            }
+            if let Some(to) = selection.to {
+                query = query.filter(created_at.lt(to));
+            }
+            if let Some(from_warehouse) = selection.warehouse {
+                query = query.filter(warehouse.eq(from_warehouse));
+            }
+            query.load::<(i32, String)>(conn)
+        })
+        .await??;
+    if labels.is_empty() {
+        return Err(AppError::OrderNotFound);
+    }
+    let mut pdfs = vec![];
+    for (shipment_id, url) in &labels {
+        pdfs.push(label_pdf(&state, *shipment_id, url).await?);
+    }
+    let sheet = tokio::task::spawn_blocking(move || merge_labels(&pdfs, selection.imposition))
+        .await?
+        .map_err(|e| AppError::Pdf(e.to_string()))?;
+    debug!("Returning sheet of {} labels", labels.len());
+    Ok(([(CONTENT_TYPE, LabelType::PdfUrl.content_type())], sheet))
+}
+
+/// returns the order and label of the shipment with this tracking number.
+#[axum::debug_handler]
+pub async fn tracking(
+    State(state): State<AppState>,
+    Tenant(name): Tenant,
+    Path(number): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    use crate::db::schema::shipments::dsl::*;
+
+    debug!("handling request \"Tracking\" for shipment n°{}", number);
+    let conn = state.pool.get().await?;
+    let shipment = conn
+        .interact(move |conn| {
+            shipments
+                .filter(tracking_number.eq(number))
+                .filter(tenant.is_not_distinct_from(name))
+                .select(Shipment::as_select())
+                .first(conn)
+                .optional()
+        })
+        .await??
+        .ok_or(AppError::TrackingNotFound)?;
+    debug!(
+        "Returning shipment of tracking n°{}",
+        shipment.tracking_number
+    );
+    Ok(Json(shipment))
+}
+
+/// returns the events of the tracing of a shipment, from the oldest.
+#[axum::debug_handler]
+pub async fn shipment_events(
+    State(state): State<AppState>,
+    Tenant(tenant): Tenant,
+    Path(shipment): Path<i32>,
+) -> Result<impl IntoResponse, AppError> {
+    debug!("handling request \"Events\" for shipment n°{}", shipment);
+    let conn = state.pool.get().await?;
+    let events = conn
+        .interact(move |conn| {
+            let exists = shipments::table
+                .find(shipment)
+                .filter(shipments::tenant.is_not_distinct_from(tenant))
+                .select(shipments::id)
+                .first::<i32>(conn)
+                .optional()?
+                .is_some();
+            if !exists {
+                return Ok(None);
+            }
+            shipment_events::table
+                .filter(shipment_events::shipment_id.eq(shipment))
+                .order(shipment_events::happened_at.asc())
+                .select(ShipmentEvent::as_select())
+                .load(conn)
+                .map(Some)
+        })
+        .await??
+        .ok_or(AppError::ShipmentNotFound)?;
+    debug!("Returning events of shipment n°{}", shipment);
+    Ok(Json(events))
+}
+
+/// reload the configuration, returns the settings that need a restart to change.
+pub async fn reload_config(State(state): State<AppState>) -> Result<Json<Reloaded>, AppError> {
+    Ok(Json(reload::reload_blocking(state).await?))
+}
+
+/// returns the shipment number and the label output of the shipments created, in the order of the request.
+/// The output is the url of the label or the code for the printer, depending on the label type.
+fn find_labels(
+    response: &ShipmentCreationResponse,
+    count: usize,
+) -> Result<Vec<(String, String)>, AppError> {
+    for status in &response.status_list.status {
+        debug!("Mondial Relay status: {status}");
+    }
+    if let Some(status) = response.errors().next() {
+        warn!("Mondial Relay rejected the shipment: {status}");
+        return Err(status.into());
+    }
+    if response.shipments().len() != count {
+        return Err(AppError::NoLabel(format!(
+            "{} Shipment(s) for {count} requested",
+            response.shipments().len()
+        )));
+    }
+    response
+        .shipments()
+        .iter()
+        .map(|shipment| {
+            let output = shipment
+                .label_output()
+                .ok_or(AppError::NoLabel("No Label".to_string()))?;
+            Ok((shipment.shipment_number.clone(), output.to_string()))
+        })
+        .collect()
 }
@@ -1,29 +1,47 @@
 use axum::{Router, routing::get};
-use config::Config;
+use config::{Config, ConfigSource};
 use db::migration::run_migrations;
 use deadpool_diesel::postgres::Pool;
+use error::AppError;
 use get_pass::get_password;
-use handler::{label, shipment};
+use handler::{
+    label, label_code, label_file, label_sheet, reload_config, return_shipment, shipment,
+    shipment_events, shipments, tracking,
+};
 use reqwest::{
     Client, ClientBuilder,
     header::{self, ACCEPT, CONTENT_TYPE},
 };
+use std::sync::{Arc, PoisonError, RwLock};
+use storage::LabelStorage;
 
 pub mod config;
 pub mod db;
 pub mod error;
 pub mod handler;
+pub mod reload;
 pub mod request;
+pub mod response;
+pub mod sheet;
+pub mod storage;
+pub mod tracker;
+pub mod webhook;
+pub mod webservice;
 
 #[derive(Clone)]
 pub struct AppState {
-    // Configuration that the program will run with.
-    // roadmap could include allowing to use command line args and environments variable.
-    pub config: Config,
+    // Configuration that the program will run with,
+    // from the file given to the command line, overridden by the environment variables and arguments.
+    // Replaced as a whole on reload, read it with config().
+    config: Arc<RwLock<Arc<Config>>>,
+    // where the configuration was loaded from, to reload it.
+    pub source: Option<ConfigSource>,
     // Database pool connections
     pub pool: Pool,
     // reqwest client to interact with Mondial Relay API
     pub client: Client,
+    // copies of the label files
+    pub storage: Arc<dyn LabelStorage>,
 }
 
 impl AppState {
@@ -55,18 +73,64 @@ impl AppState {
             .default_headers(headers)
             .build()
             .expect("value given to builder should be valid");
+        let storage = config.label_storage.build()?;
         Ok(AppState {
-            config,
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            source: None,
             pool,
             client,
+            storage,
         })
     }
+    /// keep the source of the configuration, to reload it.
+    pub fn with_source(mut self, source: ConfigSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+    /// configuration at this time, a request should read it once to not mix two versions.
+    pub fn config(&self) -> Arc<Config> {
+        self.config
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+    /// configuration of the tenant selected by a request, the default profile without one.
+    pub fn config_of(&self, tenant: Option<&str>) -> Result<Arc<Config>, AppError> {
+        let config = self.config();
+        match tenant {
+            None => Ok(config),
+            Some(name) => config
+                .tenant(name)
+                .map(Arc::new)
+                .ok_or(AppError::UnknownTenant(name.to_string())),
+        }
+    }
+    /// replace the configuration for the next readers.
+    pub fn set_config(&self, config: Config) {
+        *self.config.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(config);
+    }
 }
 pub fn router(state: AppState) -> Router {
     Router::new()
         // all endpoint must be protected by authorization gateway allowing workers but not customers.
         .route("/shipment", axum::routing::post(shipment))
+        // creates many shipments at once, returns the tracking id or the error of each order.
+        .route("/shipments", axum::routing::post(shipments))
+        // creates the label for the recipient to send the parcels of the order back, returns its tracking id.
+        .route("/return/:id_order", axum::routing::post(return_shipment))
         // returns only the url, not the full pdf. client work must then fetch the url to get the pdf.
         .route("/label/:id_order", get(label))
+        // returns the code of labels for thermal printers, to send as is to the printer.
+        .route("/label/:id_order/code", get(label_code))
+        // returns the pdf of the label, kept by the server.
+        .route("/label/:id_order/pdf", get(label_file))
+        // returns one pdf with the labels of many orders, to print them at once.
+        .route("/labels", axum::routing::post(label_sheet))
+        // customer support usually starts from the tracking number given to the customer.
+        .route("/tracking/:number", get(tracking))
+        // history of the tracing of a shipment, updated in the background.
+        .route("/shipment/:id/events", get(shipment_events))
+        // reads the configuration file again, as SIGHUP does. Reserved to the administrators.
+        .route("/admin/reload", axum::routing::post(reload_config))
         .with_state(state)
 }
@@ -1,14 +1,85 @@
+use std::{net::IpAddr, path::PathBuf};
+
 use axum::serve;
-use mondialrelay_api_lib::{AppState, router};
-use tracing::info;
+use clap::Parser;
+use mondialrelay_api_lib::{
+    AppState,
+    config::{Config, ConfigSource, ENV_PREFIX},
+    reload, router, tracker, webhook,
+};
+use tracing::{info, warn};
+
+/// configuration file read when none is given.
+const DEFAULT_CONFIG: &str = "/etc/mondialrelay/config.toml";
+/// configuration file of the former versions, read if it is the only one.
+const FORMER_CONFIG: &str = "/etc/mondialrelay-api/config.toml";
+
+/// Values are taken in this order: arguments, MONDIALRELAY_* environment variables, configuration file, defaults.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// path of the configuration file, which must exist. Default to /etc/mondialrelay/config.toml,
+    /// or /etc/mondialrelay-api/config.toml if only this one exists
+    #[arg(short, long, env = "MONDIALRELAY_CONFIG")]
+    config: Option<PathBuf>,
+    /// address on which the API will listen
+    #[arg(long)]
+    listen_address: Option<IpAddr>,
+    /// port on which the API will listen
+    #[arg(short, long)]
+    port: Option<u16>,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing_subscriber::fmt::init();
-    let state = AppState::new(confy::load_path("/etc/mondialrelay-api/config.toml")?).await?;
+    let cli = Cli::parse();
+    // the arguments are applied as overrides, to be applied again on reload.
+    let mut arguments = vec![];
+    if let Some(address) = cli.listen_address {
+        arguments.push((format!("{ENV_PREFIX}LISTEN_ADDRESS"), address.to_string()));
+    }
+    if let Some(port) = cli.port {
+        arguments.push((format!("{ENV_PREFIX}LISTEN_PORT"), port.to_string()));
+    }
+    let path = match cli.config {
+        Some(path) => path,
+        None => default_config()?,
+    };
+    let source = ConfigSource { path, arguments };
+    let config = source.load()?;
+    // refuse to start rather than failing on the first shipment.
+    config
+        .validate()
+        .map_err(|problems| format!("Invalid configuration:\n- {}", problems.join("\n- ")))?;
+    let state = AppState::new(config).await?.with_source(source);
+    let config = state.config();
     let listener =
-        tokio::net::TcpListener::bind(format!("127.0.0.1:{}", state.config.listen_port)).await?;
-    info!("Listening on port {}", state.config.listen_port);
+        tokio::net::TcpListener::bind((config.listen_address, config.listen_port)).await?;
+    // both can be enabled by a reload.
+    tokio::spawn(tracker::run(state.clone()));
+    tokio::spawn(webhook::run(state.clone()));
+    tokio::spawn(reload::on_hangup(state.clone()));
+    info!(
+        "Listening on {}:{}",
+        config.listen_address, config.listen_port
+    );
     serve(listener, router(state)).await?;
     Ok(())
 }
+
+/// the default configuration file, or the one of the former versions if only this one exists.
+/// The default file is created if there is none.
+fn default_config() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = PathBuf::from(DEFAULT_CONFIG);
+    let former = PathBuf::from(FORMER_CONFIG);
+    if !path.exists() && former.exists() {
+        warn!("Reading the configuration from {FORMER_CONFIG}, move it to {DEFAULT_CONFIG}");
+        return Ok(former);
+    }
+    if !path.exists() {
+        warn!("Creating {DEFAULT_CONFIG} with the default values");
+        confy::store_path(&path, Config::default())?;
+    }
+    Ok(path)
+}
@@ -0,0 +1,60 @@
+// Reload of the configuration while the server runs, on SIGHUP or from the admin endpoint.
+// Requests already started keep the configuration they started with.
+
+use serde::Serialize;
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::{info, warn};
+
+use crate::{AppState, error::AppError};
+
+#[derive(Serialize, Debug)]
+pub struct Reloaded {
+    // settings changed in the file that are only applied after a restart
+    pub restart_required: Vec<&'static str>,
+}
+
+/// read and validate the configuration again, then apply it to the new requests.
+/// The current configuration is kept if the new one is invalid.
+pub fn reload(state: &AppState) -> Result<Reloaded, AppError> {
+    let source = state.source.as_ref().ok_or(AppError::InvalidConfig(
+        "The configuration was not loaded from a file".to_string(),
+    ))?;
+    let config = source
+        .load()
+        .map_err(|e| AppError::InvalidConfig(e.to_string()))?;
+    config
+        .validate()
+        .map_err(|problems| AppError::InvalidConfig(problems.join(", ")))?;
+    let (config, restart_required) = state.config().reloaded(config);
+    state.set_config(config);
+    if restart_required.is_empty() {
+        info!("Configuration reloaded");
+    } else {
+        warn!(
+            "Configuration reloaded, a restart is required to apply {}",
+            restart_required.join(", ")
+        );
+    }
+    Ok(Reloaded { restart_required })
+}
+
+/// reload on a blocking thread, reading the file and the secrets must not hold the requests.
+pub async fn reload_blocking(state: AppState) -> Result<Reloaded, AppError> {
+    tokio::task::spawn_blocking(move || reload(&state)).await?
+}
+
+/// reload the configuration at each SIGHUP, forever.
+pub async fn on_hangup(state: AppState) {
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            warn!("Could not listen to SIGHUP, the configuration can not be reloaded: {e}");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        if let Err(e) = reload_blocking(state.clone()).await {
+            warn!("{e}");
+        }
+    }
+}
@@ -1,7 +1,7 @@
 // Generated by https://github.com/lumeohq/xsd-parser-rs cli from view-source:https://www.mondialrelay.fr/media/51911/Mondial-Relay-Shipment-API-.Request.1.0.xsd
 
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use std::{collections::BTreeMap, str::FromStr};
 use xsd_macro_utils::{UtilsDefaultSerde, UtilsTupleIo};
 use xsd_parser::generator::validator::Validate;
 use yaserde::{YaDeserialize, YaSerialize};
@@ -31,65 +31,28 @@ impl Validate for ShipmentCreationRequest {
 }
 
 impl ShipmentCreationRequest {
-    pub fn new(config: Config, data: NewShipment) -> Result<Self, AppError> {
+    /// request creating the shipments, which all share the same label output.
+    pub fn new(
+        config: &Config,
+        output_options: OutputOptions,
+        shipments: Vec<Shipment>,
+    ) -> Result<Self, AppError> {
         Ok(ShipmentCreationRequest {
             context: config
                 .context_api_mondialrelay()
                 .map_err(|_| AppError::Conf)?,
-            output_options: OutputOptions {
-                output_format: output_options_type::OutputFormat(config.format.clone()),
-                output_type: output_options_type::OutputType("PdfUrl".to_string()),
-            },
+            output_options,
             shipments_list: ShipmentsList {
-                shipment: vec![Shipment {
-                    // MondialRelay doesn't need to know our customer id nor order id
-                    order_no: None,
-                    customer_no: None,
-                    parcel_count: shipment_type::ParcelCount(1),
-                    shipment_value: None,
-                    options: None,
-                    delivery_mode: ProductConfiguration {
-                        mode: data.delivery_mode,
-                        location: data.delivery_location,
-                    },
-                    collection_mode: ProductConfiguration {
-                        mode: "CCC".to_string(),
-                        location: None,
-                    },
-                    parcels: ParcelList {
-                        parcel: vec![Parcel {
-                            content: None,
-                            length: MeasureAmount {
-                                value: data.length as f64,
-                                unit: "cm".to_string(),
-                            },
-                            width: MeasureAmount {
-                                value: data.width as f64,
-                                unit: "cm".to_string(),
-                            },
-                            depth: MeasureAmount {
-                                value: data.depth as f64,
-                                unit: "cm".to_string(),
-                            },
-                            weight: MeasureAmount {
-                                value: data.weight as f64,
-                                unit: "gr".to_string(),
-                            },
-                        }],
-                    },
-                    delivery_instruction: data
-                        .delivery_instructions
-                        .map(shipment_type::DeliveryInstruction),
-                    sender: SenderDetails {
-                        address: config.sender_address(),
-                    },
-                    recipient: crate::request::RecipientDetails {
-                        address: data.recipient_details,
-                    },
-                }],
+                shipment: shipments,
             },
         })
     }
+    /// copy of the request without the password, to be stored.
+    pub fn redacted(&self) -> Self {
+        let mut request = self.clone();
+        request.context.password = "********".to_string();
+        request
+    }
 }
 
 #[derive(Default, Clone, PartialEq, Debug, YaDeserialize, YaSerialize)]
@@ -125,7 +88,13 @@ pub struct Context {
     pub version_api: context_type::VersionAPI,
 }
 
-impl Validate for Context {}
+impl Validate for Context {
+    fn validate(&self) -> Result<(), String> {
+        self.customer_id.validate()?;
+        self.culture.validate()?;
+        Ok(())
+    }
+}
 
 pub mod context_type {
 
@@ -156,6 +125,12 @@ pub mod context_type {
     #[derive(Default, Clone, PartialEq, Debug, UtilsTupleIo, UtilsDefaultSerde)]
     pub struct Culture(pub String);
 
+    /// Cultures of the countries served by Mondial Relay.
+    pub const CULTURES: [&str; 11] = [
+        "fr-FR", "fr-BE", "nl-BE", "nl-NL", "en-GB", "en-US", "es-ES", "de-DE", "it-IT", "pt-PT",
+        "pl-PL",
+    ];
+
     impl Validate for Culture {
         fn validate(&self) -> Result<(), String> {
             if self.0.len() != 5 {
@@ -164,6 +139,13 @@ pub mod context_type {
                     self.0.len()
                 ));
             }
+            if !CULTURES.contains(&self.0.as_str()) {
+                return Err(format!(
+                    "Enumeration validation error. \nExpected: {} \nActual: {}",
+                    CULTURES.join(", "),
+                    self.0
+                ));
+            }
             Ok(())
         }
     }
@@ -186,7 +168,36 @@ pub struct OutputOptions {
     pub output_type: output_options_type::OutputType,
 }
 
-impl Validate for OutputOptions {}
+impl OutputOptions {
+    pub fn new(label_type: output_options_type::LabelType, format: String) -> Self {
+        OutputOptions {
+            output_format: output_options_type::OutputFormat(format),
+            output_type: output_options_type::OutputType(label_type.as_str().to_string()),
+        }
+    }
+}
+
+impl Validate for OutputOptions {
+    fn validate(&self) -> Result<(), String> {
+        match output_options_type::LabelType::from_str(&self.output_type.0)? {
+            output_options_type::LabelType::PdfUrl => {
+                if !["A4", "A5", "10x15"].contains(&self.output_format.0.as_str()) {
+                    return Err(format!(
+                        "Enumeration validation error. \nExpected: A4, A5 or 10x15 \nActual: {}",
+                        self.output_format.0
+                    ));
+                }
+            }
+            // printer model, Mondial Relay can add new ones on demand.
+            output_options_type::LabelType::ZplCode | output_options_type::LabelType::IplCode => {
+                if self.output_format.0.is_empty() {
+                    return Err("A printer model is required for ZplCode and IplCode".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+}
 
 pub mod output_options_type {
     use super::*;
@@ -207,6 +218,50 @@ pub mod output_options_type {
     // The expected output format. Supported output format
     // list: ‘ZplCode’, ‘PdfUrl’, ‘IplCode’.
     impl Validate for OutputType {}
+
+    /// Supported values of OutputType.
+    #[derive(Default, Clone, Copy, PartialEq, Debug, Deserialize, Serialize)]
+    pub enum LabelType {
+        // url of a pdf file
+        #[default]
+        PdfUrl,
+        // code for thermal printers using ZPL
+        ZplCode,
+        // code for thermal printers using IPL
+        IplCode,
+    }
+
+    impl LabelType {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                LabelType::PdfUrl => "PdfUrl",
+                LabelType::ZplCode => "ZplCode",
+                LabelType::IplCode => "IplCode",
+            }
+        }
+        /// content type of the label returned by Mondial Relay.
+        pub fn content_type(&self) -> &'static str {
+            match self {
+                LabelType::PdfUrl => "application/pdf",
+                LabelType::ZplCode => "application/x-zpl",
+                LabelType::IplCode => "application/x-ipl",
+            }
+        }
+    }
+
+    impl FromStr for LabelType {
+        type Err = String;
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "PdfUrl" => Ok(LabelType::PdfUrl),
+                "ZplCode" => Ok(LabelType::ZplCode),
+                "IplCode" => Ok(LabelType::IplCode),
+                _ => Err(format!(
+                    "Enumeration validation error. \nExpected: PdfUrl, ZplCode or IplCode \nActual: {s}"
+                )),
+            }
+        }
+    }
 }
 
 #[derive(Default, Clone, PartialEq, Debug, YaDeserialize, YaSerialize)]
@@ -215,7 +270,23 @@ pub struct ShipmentsList {
     pub shipment: Vec<Shipment>,
 }
 
-impl Validate for ShipmentsList {}
+impl Validate for ShipmentsList {
+    fn validate(&self) -> Result<(), String> {
+        if self.shipment.is_empty() || self.shipment.len() > MAX_SHIPMENTS {
+            return Err(format!(
+                "A request holds between 1 and {MAX_SHIPMENTS} shipments, {} given",
+                self.shipment.len()
+            ));
+        }
+        for shipment in &self.shipment {
+            shipment.validate()?;
+        }
+        Ok(())
+    }
+}
+
+/// maximum number of shipments created by one request to Mondial Relay.
+pub const MAX_SHIPMENTS: usize = 100;
 
 #[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
 pub struct Shipment {
@@ -262,7 +333,129 @@ pub struct Shipment {
     pub recipient: RecipientDetails,
 }
 
-impl Validate for Shipment {}
+impl Validate for Shipment {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(order_no) = &self.order_no {
+            order_no.validate()?;
+        }
+        if let Some(customer_no) = &self.customer_no {
+            customer_no.validate()?;
+        }
+        if let Some(value) = &self.shipment_value {
+            value.validate()?;
+        }
+        if let Some(options) = &self.options {
+            options.validate()?;
+        }
+        self.sender.validate()?;
+        self.recipient.validate()?;
+        Ok(())
+    }
+}
+
+impl Shipment {
+    /// shipment sending back the parcels of this one, from the recipient to the sender.
+    /// The recipient drops the parcels off at the Point Relais of the location.
+    pub fn return_of(&self, config: &Config, location: String) -> Self {
+        Shipment {
+            order_no: self.order_no.clone(),
+            customer_no: self.customer_no.clone(),
+            parcel_count: self.parcel_count.clone(),
+            shipment_value: self.shipment_value.clone(),
+            options: None,
+            delivery_mode: ProductConfiguration {
+                mode: product_configuration_type::DeliveryMode::Merchant
+                    .as_str()
+                    .to_string(),
+                location: None,
+            },
+            collection_mode: ProductConfiguration {
+                mode: product_configuration_type::CollectionMode::Relay
+                    .as_str()
+                    .to_string(),
+                location: Some(location),
+            },
+            parcels: self.parcels.clone(),
+            delivery_instruction: None,
+            sender: SenderDetails {
+                address: self.recipient.address.clone(),
+            },
+            recipient: RecipientDetails {
+                address: config.sender_address(),
+            },
+        }
+    }
+    pub fn new(config: &Config, data: NewShipment) -> Result<Self, AppError> {
+        let collection_mode = config
+            .collection(data.collection_mode.unwrap_or(config.collection_mode))
+            .map_err(AppError::InvalidMode)?;
+        // MondialRelay doesn't need to know our customer id nor order id,
+        // but shows them on invoices when they are shared.
+        let (order_no, customer_no) = if config.share_references {
+            (
+                data.order_no.map(shipment_type::OrderNo),
+                data.customer_no.map(shipment_type::CustomerNo),
+            )
+        } else {
+            (None, None)
+        };
+        if let Some(order_no) = &order_no {
+            order_no.validate().map_err(AppError::InvalidReference)?;
+        }
+        if let Some(customer_no) = &customer_no {
+            customer_no.validate().map_err(AppError::InvalidReference)?;
+        }
+        Ok(Shipment {
+            order_no,
+            customer_no,
+            parcel_count: shipment_type::ParcelCount(data.parcels.len() as i32),
+            shipment_value: data.value.map(|value| MonetaryAmount {
+                currency: Some(value.currency),
+                amount: Some(value.amount as f64),
+            }),
+            options: OptionList::new(data.insurance, data.options),
+            delivery_mode: ProductConfiguration {
+                mode: data.delivery_mode.as_str().to_string(),
+                location: data.delivery_location,
+            },
+            collection_mode,
+            parcels: ParcelList {
+                parcel: data
+                    .parcels
+                    .into_iter()
+                    .map(|parcel| Parcel {
+                        content: parcel.content,
+                        length: MeasureAmount {
+                            value: parcel.length as f64,
+                            unit: "cm".to_string(),
+                        },
+                        width: MeasureAmount {
+                            value: parcel.width as f64,
+                            unit: "cm".to_string(),
+                        },
+                        depth: MeasureAmount {
+                            value: parcel.depth as f64,
+                            unit: "cm".to_string(),
+                        },
+                        weight: MeasureAmount {
+                            value: parcel.weight as f64,
+                            unit: "gr".to_string(),
+                        },
+                    })
+                    .collect(),
+            },
+            delivery_instruction: data
+                .delivery_instructions
+                .map(shipment_type::DeliveryInstruction),
+            sender: SenderDetails {
+                address: config.sender_address(),
+            },
+            recipient: RecipientDetails {
+                address: data.recipient_details,
+            },
+        })
+    }
+}
 
 pub mod shipment_type {
     use super::*;
@@ -334,7 +527,26 @@ pub struct MonetaryAmount {
     pub amount: Option<f64>,
 }
 
-impl Validate for MonetaryAmount {}
+impl Validate for MonetaryAmount {
+    fn validate(&self) -> Result<(), String> {
+        match &self.currency {
+            Some(currency)
+                if currency.len() == 3 && currency.bytes().all(|c| c.is_ascii_uppercase()) => {}
+            currency => {
+                return Err(format!(
+                    "Currency validation error. \nExpected: ISO 4217 code, like EUR \nActual: {}",
+                    currency.as_deref().unwrap_or_default()
+                ));
+            }
+        }
+        match self.amount {
+            Some(amount) if amount >= 0.0 => Ok(()),
+            amount => Err(format!(
+                "Amount validation error. \nExpected: positive amount in cents \nActual: {amount:?}"
+            )),
+        }
+    }
+}
 
 #[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
 pub struct OptionList {
@@ -342,7 +554,46 @@ pub struct OptionList {
     pub option: Vec<KeyValue>,
 }
 
-impl Validate for OptionList {}
+impl Validate for OptionList {
+    fn validate(&self) -> Result<(), String> {
+        for (i, option) in self.option.iter().enumerate() {
+            option.validate()?;
+            if self.option[..i].iter().any(|o| o.key == option.key) {
+                return Err(format!(
+                    "Option {} is given twice",
+                    option.key.as_deref().unwrap_or_default()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OptionList {
+    /// options of a shipment, None if there is none.
+    pub fn new(insurance: Option<u8>, options: BTreeMap<String, String>) -> Option<Self> {
+        let option = insurance
+            .map(|level| (option_key::INSURANCE.to_string(), level.to_string()))
+            .into_iter()
+            .chain(options)
+            .map(|(key, value)| KeyValue {
+                key: Some(key),
+                value: Some(value),
+            })
+            .collect::<Vec<_>>();
+        (!option.is_empty()).then_some(OptionList { option })
+    }
+}
+
+/// keys of the options accepted by Mondial Relay.
+pub mod option_key {
+    // level of insurance, from 0 (none) to 5
+    pub const INSURANCE: &str = "INS";
+    // amount to collect on delivery, in cents
+    pub const CASH_ON_DELIVERY: &str = "CRT";
+    // language of the notifications sent to the recipient
+    pub const LANGUAGE: &str = "LNG";
+}
 
 #[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
 pub struct KeyValue {
@@ -353,7 +604,24 @@ pub struct KeyValue {
     pub value: Option<String>,
 }
 
-impl Validate for KeyValue {}
+impl Validate for KeyValue {
+    fn validate(&self) -> Result<(), String> {
+        let key = self.key.as_deref().unwrap_or_default();
+        let value = self.value.as_deref().unwrap_or_default();
+        let valid = match key {
+            option_key::INSURANCE => matches!(value, "0" | "1" | "2" | "3" | "4" | "5"),
+            option_key::CASH_ON_DELIVERY => {
+                !value.is_empty() && value.bytes().all(|c| c.is_ascii_digit())
+            }
+            option_key::LANGUAGE => ["FR", "EN", "ES", "NL", "DE", "IT", "PT"].contains(&value),
+            _ => return Err(format!("Unknown option {key}")),
+        };
+        if !valid {
+            return Err(format!("Invalid value {value} for option {key}"));
+        }
+        Ok(())
+    }
+}
 
 #[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
 pub struct ProductConfiguration {
@@ -382,6 +650,133 @@ pub struct ProductConfiguration {
 
 impl Validate for ProductConfiguration {}
 
+pub mod product_configuration_type {
+    use super::*;
+
+    /// Delivery modes of Mondial Relay.
+    #[derive(Clone, Copy, PartialEq, Debug, Deserialize, Serialize)]
+    pub enum DeliveryMode {
+        // Point Relais®
+        #[serde(rename = "24R")]
+        Relay,
+        // Point Relais® XL
+        #[serde(rename = "24L")]
+        RelayXl,
+        // Point Relais® XXL
+        #[serde(rename = "24X")]
+        RelayXxl,
+        // locker
+        #[serde(rename = "APM")]
+        Locker,
+        // drive of a Point Relais®
+        #[serde(rename = "DRI")]
+        Drive,
+        // home delivery
+        #[serde(rename = "HOM")]
+        Home,
+        // delivery to the merchant, for returns
+        #[serde(rename = "LCC")]
+        Merchant,
+        // home delivery by one delivery man, for heavy parcels
+        #[serde(rename = "LD1")]
+        HomeOneMan,
+        // home delivery by two delivery men, for bulky parcels
+        #[serde(rename = "LDS")]
+        HomeTwoMen,
+        // D+1 home delivery, needs the EDI file "leaving warehouse"
+        #[serde(rename = "XOH")]
+        HomeNextDay,
+    }
+
+    impl DeliveryMode {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                DeliveryMode::Relay => "24R",
+                DeliveryMode::RelayXl => "24L",
+                DeliveryMode::RelayXxl => "24X",
+                DeliveryMode::Locker => "APM",
+                DeliveryMode::Drive => "DRI",
+                DeliveryMode::Home => "HOM",
+                DeliveryMode::Merchant => "LCC",
+                DeliveryMode::HomeOneMan => "LD1",
+                DeliveryMode::HomeTwoMen => "LDS",
+                DeliveryMode::HomeNextDay => "XOH",
+            }
+        }
+        /// the parcels are delivered to a location (relay, locker or drive), not to the address.
+        pub fn needs_location(&self) -> bool {
+            matches!(
+                self,
+                DeliveryMode::Relay
+                    | DeliveryMode::RelayXl
+                    | DeliveryMode::RelayXxl
+                    | DeliveryMode::Locker
+                    | DeliveryMode::Drive
+            )
+        }
+        /// Mondial Relay can choose the relay near the address.
+        pub fn allows_auto(&self) -> bool {
+            matches!(
+                self,
+                DeliveryMode::Relay | DeliveryMode::RelayXl | DeliveryMode::RelayXxl
+            )
+        }
+        /// maximum weight of a parcel, in gr.
+        pub fn max_weight(&self) -> u32 {
+            match self {
+                DeliveryMode::Locker => 20_000,
+                DeliveryMode::Relay
+                | DeliveryMode::RelayXl
+                | DeliveryMode::Home
+                | DeliveryMode::Merchant
+                | DeliveryMode::HomeNextDay => 30_000,
+                DeliveryMode::HomeOneMan => 60_000,
+                DeliveryMode::RelayXxl => 70_000,
+                DeliveryMode::Drive | DeliveryMode::HomeTwoMen => 130_000,
+            }
+        }
+        /// maximum number of parcels in one shipment.
+        pub fn max_parcel_count(&self) -> usize {
+            match self {
+                // multi-parcels shipments are refused for Point Relais and lockers.
+                DeliveryMode::Relay
+                | DeliveryMode::RelayXl
+                | DeliveryMode::RelayXxl
+                | DeliveryMode::Locker => 1,
+                // maximum of the schema
+                _ => 99,
+            }
+        }
+    }
+
+    /// How Mondial Relay collects the parcels of the sender.
+    #[derive(Default, Clone, Copy, PartialEq, Debug, Deserialize, Serialize)]
+    pub enum CollectionMode {
+        // picked up at the sender
+        #[default]
+        #[serde(rename = "CCC")]
+        Merchant,
+        // dropped off by the sender at its Point Relais®
+        #[serde(rename = "REL")]
+        Relay,
+    }
+
+    impl CollectionMode {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                CollectionMode::Merchant => "CCC",
+                CollectionMode::Relay => "REL",
+            }
+        }
+    }
+
+    impl std::fmt::Display for DeliveryMode {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(self.as_str())
+        }
+    }
+}
+
 #[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
 pub struct ParcelList {
     // each parcel of the shipment will be described with an element
@@ -423,7 +818,9 @@ impl Validate for Parcel {}
 pub mod parcel_type {
     use super::*;
 
-    #[derive(Default, Clone, PartialEq, Debug, UtilsTupleIo, UtilsDefaultSerde)]
+    #[derive(
+        Default, Clone, PartialEq, Debug, UtilsTupleIo, UtilsDefaultSerde, Deserialize, Serialize,
+    )]
     pub struct Content(pub String);
 
     impl Validate for Content {
@@ -456,7 +853,11 @@ pub struct SenderDetails {
     pub address: Address,
 }
 
-impl Validate for SenderDetails {}
+impl Validate for SenderDetails {
+    fn validate(&self) -> Result<(), String> {
+        self.address.validate()
+    }
+}
 
 #[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
 pub struct RecipientDetails {
@@ -464,7 +865,11 @@ pub struct RecipientDetails {
     pub address: Address,
 }
 
-impl Validate for RecipientDetails {}
+impl Validate for RecipientDetails {
+    fn validate(&self) -> Result<(), String> {
+        self.address.validate()
+    }
+}
 
 #[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize, Deserialize, Serialize)]
 pub struct Address {
@@ -526,7 +931,62 @@ pub struct Address {
     pub email: Option<address_type::Email>,
 }
 
-impl Validate for Address {}
+impl Validate for Address {
+    /// every invalid field is reported, not only the first one.
+    fn validate(&self) -> Result<(), String> {
+        let mut problems = vec![];
+        let mut check = |field: &str, result: Result<(), String>| {
+            if let Err(e) = result {
+                problems.push(format!("{field}: {e}"));
+            }
+        };
+        if let Some(title) = &self.title {
+            check("Title", title.validate());
+        }
+        if let Some(firstname) = &self.firstname {
+            check("Firstname", firstname.validate());
+        }
+        if let Some(lastname) = &self.lastname {
+            check("Lastname", lastname.validate());
+        }
+        if self.streetname.len() > 30 {
+            check(
+                "Streetname",
+                Err(format!(
+                    "MaxLength validation error. \nExpected: Streetname length <= 30 \nActual: Streetname length == {}",
+                    self.streetname.len()
+                )),
+            );
+        }
+        if let Some(house_no) = &self.house_no {
+            check("HouseNo", house_no.validate());
+        }
+        check("CountryCode", self.country_code.validate());
+        check("PostCode", self.post_code.validate());
+        check("City", self.city.validate());
+        if let Some(address_add_1) = &self.address_add_1 {
+            check("AddressAdd1", address_add_1.validate());
+        }
+        if let Some(address_add_2) = &self.address_add_2 {
+            check("AddressAdd2", address_add_2.validate());
+        }
+        if let Some(address_add_3) = &self.address_add_3 {
+            check("AddressAdd3", address_add_3.validate());
+        }
+        check("PhoneNo", self.phone_no.validate());
+        if let Some(mobile_no) = &self.mobile_no {
+            check("MobileNo", mobile_no.validate());
+        }
+        if let Some(email) = &self.email {
+            check("Email", email.validate());
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.join("; "))
+        }
+    }
+}
 
 pub mod address_type {
     use super::*;
@@ -613,6 +1073,12 @@ pub mod address_type {
                     self.0.len()
                 ));
             }
+            if !self.0.chars().all(|c| c.is_ascii_uppercase()) {
+                return Err(format!(
+                    "Pattern validation error. \nExpected: two uppercase letters \nActual: {}",
+                    self.0
+                ));
+            }
             // check every country code ?
             Ok(())
         }
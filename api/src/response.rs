@@ -0,0 +1,128 @@
+// Model of the response returned by Mondial Relay to a ShipmentCreationRequest.
+// Follows the Response schema of the Shipment API 1.0, counterpart of request.rs.
+
+use std::fmt;
+
+use yaserde::{YaDeserialize, YaSerialize};
+
+#[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
+#[yaserde(namespaces = {"xsi" = "http://www.w3.org/2001/XMLSchema-instance", "xsd" = "http://www.w3.org/2001/XMLSchema", "" = "http://www.example.org/Response"})]
+pub struct ShipmentCreationResponse {
+    // Result of the processing of the request.
+    // Contains at least one status, even if the request succeeded.
+    #[yaserde(rename = "StatusList")]
+    pub status_list: StatusList,
+
+    // Absent if the request was rejected.
+    #[yaserde(rename = "ShipmentsList")]
+    pub shipments_list: Option<ShipmentsList>,
+}
+
+impl ShipmentCreationResponse {
+    /// statuses with a level of error, explaining why the request was rejected.
+    pub fn errors(&self) -> impl Iterator<Item = &Status> {
+        self.status_list
+            .status
+            .iter()
+            .filter(|status| status.is_error())
+    }
+    /// shipments created by Mondial Relay, in the order of the request.
+    pub fn shipments(&self) -> &[Shipment] {
+        self.shipments_list
+            .as_ref()
+            .map(|list| list.shipment.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
+pub struct StatusList {
+    #[yaserde(rename = "Status")]
+    pub status: Vec<Status>,
+}
+
+#[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
+pub struct Status {
+    // Code of the status, 0 if the request was processed successfully.
+    #[yaserde(attribute = true, rename = "Code")]
+    pub code: String,
+
+    // Error, Warning or Info
+    #[yaserde(attribute = true, rename = "Level")]
+    pub level: String,
+
+    // Human readable description of the status, in the culture of the request.
+    #[yaserde(attribute = true, rename = "Message")]
+    pub message: String,
+}
+
+impl Status {
+    pub fn is_error(&self) -> bool {
+        self.level.eq_ignore_ascii_case("Error")
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.level, self.code, self.message)
+    }
+}
+
+#[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
+pub struct ShipmentsList {
+    #[yaserde(rename = "Shipment")]
+    pub shipment: Vec<Shipment>,
+}
+
+#[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
+pub struct Shipment {
+    // Number given by Mondial Relay to the shipment, used to track it.
+    #[yaserde(attribute = true, rename = "ShipmentNumber")]
+    pub shipment_number: String,
+
+    #[yaserde(rename = "LabelList")]
+    pub label_list: LabelList,
+}
+
+impl Shipment {
+    /// output of the first label of the shipment.
+    pub fn label_output(&self) -> Option<&str> {
+        self.label_list
+            .label
+            .first()
+            .map(|label| label.output.as_str())
+    }
+}
+
+#[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
+pub struct LabelList {
+    #[yaserde(rename = "Label")]
+    pub label: Vec<Label>,
+}
+
+#[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
+pub struct Label {
+    // Content depends on the OutputType of the request:
+    // the url of the label for PdfUrl, the printer code for ZplCode and IplCode.
+    #[yaserde(rename = "Output")]
+    pub output: String,
+
+    // Values printed on the label, useful to print it by our own means.
+    #[yaserde(rename = "RawContent")]
+    pub raw_content: Option<RawContent>,
+}
+
+#[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
+pub struct RawContent {
+    #[yaserde(rename = "LabelValues")]
+    pub label_values: Vec<LabelValues>,
+}
+
+#[derive(Default, Clone, PartialEq, Debug, YaSerialize, YaDeserialize)]
+pub struct LabelValues {
+    #[yaserde(attribute = true, rename = "Key")]
+    pub key: String,
+
+    #[yaserde(attribute = true, rename = "Value")]
+    pub value: String,
+}
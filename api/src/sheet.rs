@@ -0,0 +1,200 @@
+// Sheets of labels, to print the labels of many orders at once.
+// Every page of the labels is turned into a form, which is then drawn on the pages of the sheet.
+
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream, dictionary};
+use serde::{Deserialize, Serialize};
+
+// size of an A4 page in points
+const A4: (f32, f32) = (595.0, 842.0);
+
+/// How the labels are placed on the pages of the sheet.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Imposition {
+    // one label per page, with the size of the label.
+    #[default]
+    None,
+    // two labels per A4 page, for A5 labels.
+    Two,
+    // four labels per A4 page, for 10x15 labels.
+    Four,
+}
+
+impl Imposition {
+    // columns and rows of labels on an A4 page.
+    fn grid(&self) -> Option<(usize, usize)> {
+        match self {
+            Imposition::None => None,
+            Imposition::Two => Some((1, 2)),
+            Imposition::Four => Some((2, 2)),
+        }
+    }
+}
+
+// a label drawn on a page: form and matrix placing it.
+struct Placement {
+    form: ObjectId,
+    matrix: [f32; 6],
+}
+
+/// merge the pdf of the labels in one pdf, in the same order.
+pub fn merge_labels(labels: &[Vec<u8>], imposition: Imposition) -> Result<Vec<u8>, lopdf::Error> {
+    let mut sheet = Document::with_version("1.5");
+    let pages_id = sheet.new_object_id();
+    let mut forms = vec![];
+    for label in labels {
+        let mut doc = Document::load_mem(label)?;
+        doc.renumber_objects_with(sheet.max_id + 1);
+        for page_id in doc.get_pages().into_values() {
+            let bbox = media_box(&doc, page_id)?;
+            let mut form = dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Form",
+                "BBox" => bbox.iter().map(|v| Object::Real(*v)).collect::<Vec<_>>(),
+            };
+            if let Some(resources) = inherited(&doc, page_id, b"Resources") {
+                form.set("Resources", resources);
+            }
+            forms.push((Stream::new(form, doc.get_page_content(page_id)?), bbox));
+        }
+        sheet.max_id = doc.max_id;
+        sheet.objects.extend(doc.objects);
+    }
+    let forms = forms
+        .into_iter()
+        .map(|(form, bbox)| (sheet.add_object(form), bbox))
+        .collect::<Vec<_>>();
+
+    let mut kids = vec![];
+    match imposition.grid() {
+        None => {
+            for (form, bbox) in forms {
+                let placement = Placement {
+                    form,
+                    matrix: [1.0, 0.0, 0.0, 1.0, 0.0, 0.0],
+                };
+                kids.push(add_page(&mut sheet, pages_id, bbox, &[placement]));
+            }
+        }
+        Some((columns, rows)) => {
+            let (width, height) = (A4.0 / columns as f32, A4.1 / rows as f32);
+            for page in forms.chunks(columns * rows) {
+                let placements = page
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (form, bbox))| {
+                        let (column, row) = ((i % columns) as f32, (i / columns) as f32);
+                        let (w, h) = (bbox[2] - bbox[0], bbox[3] - bbox[1]);
+                        // a label across its cell is turned a quarter, to be printed bigger:
+                        // A5 labels are portrait, the cells of two labels per page landscape.
+                        let rotate = (w > h) != (width > height);
+                        let (w, h) = if rotate { (h, w) } else { (w, h) };
+                        // fit the label in its cell, centered, keeping its ratio.
+                        let scale = (width / w).min(height / h);
+                        let left = column * width + (width - w * scale) / 2.0;
+                        let bottom = A4.1 - (row + 1.0) * height + (height - h * scale) / 2.0;
+                        let matrix = if rotate {
+                            // counterclockwise, the bottom of the label goes to the right.
+                            [
+                                0.0,
+                                scale,
+                                -scale,
+                                0.0,
+                                left + bbox[3] * scale,
+                                bottom - bbox[0] * scale,
+                            ]
+                        } else {
+                            [
+                                scale,
+                                0.0,
+                                0.0,
+                                scale,
+                                left - bbox[0] * scale,
+                                bottom - bbox[1] * scale,
+                            ]
+                        };
+                        Placement {
+                            form: *form,
+                            matrix,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                kids.push(add_page(
+                    &mut sheet,
+                    pages_id,
+                    [0.0, 0.0, A4.0, A4.1],
+                    &placements,
+                ));
+            }
+        }
+    }
+
+    let count = kids.len() as i64;
+    sheet.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => kids.into_iter().map(Object::from).collect::<Vec<_>>(),
+            "Count" => count,
+        }),
+    );
+    let catalog_id = sheet.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    sheet.trailer.set("Root", catalog_id);
+    // pages and catalogs of the labels are not used anymore.
+    sheet.prune_objects();
+    sheet.compress();
+    let mut pdf = vec![];
+    sheet.save_to(&mut pdf)?;
+    Ok(pdf)
+}
+
+fn add_page(
+    sheet: &mut Document,
+    pages_id: ObjectId,
+    media_box: [f32; 4],
+    placements: &[Placement],
+) -> ObjectId {
+    let mut xobjects = Dictionary::new();
+    let mut content = String::new();
+    for (i, placement) in placements.iter().enumerate() {
+        let name = format!("Label{i}");
+        let [a, b, c, d, e, f] = placement.matrix;
+        content.push_str(&format!("q {a} {b} {c} {d} {e} {f} cm /{name} Do Q\n"));
+        xobjects.set(name, placement.form);
+    }
+    let content_id = sheet.add_object(Stream::new(dictionary! {}, content.into_bytes()));
+    sheet.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_id,
+        "MediaBox" => media_box.iter().map(|v| Object::Real(*v)).collect::<Vec<_>>(),
+        "Contents" => content_id,
+        "Resources" => dictionary! { "XObject" => xobjects },
+    })
+}
+
+// value of an attribute of a page, which can be inherited from its parents.
+fn inherited(doc: &Document, page_id: ObjectId, key: &[u8]) -> Option<Object> {
+    let mut node = doc.get_dictionary(page_id).ok()?;
+    loop {
+        if let Ok(value) = node.get(key) {
+            return Some(value.clone());
+        }
+        node = doc
+            .get_dictionary(node.get(b"Parent").ok()?.as_reference().ok()?)
+            .ok()?;
+    }
+}
+
+fn media_box(doc: &Document, page_id: ObjectId) -> Result<[f32; 4], lopdf::Error> {
+    let media_box = inherited(doc, page_id, b"MediaBox")
+        .ok_or(lopdf::Error::DictKey("MediaBox".to_string()))?;
+    let values = doc.dereference(&media_box)?.1.as_array()?;
+    let mut bbox = [0.0; 4];
+    for (value, object) in bbox.iter_mut().zip(values) {
+        *value = doc.dereference(object)?.1.as_float()?;
+    }
+    Ok(bbox)
+}
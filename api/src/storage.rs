@@ -0,0 +1,69 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Where the label files downloaded from Mondial Relay are kept,
+/// so they stay available if Mondial Relay url expires.
+pub trait LabelStorage: Send + Sync {
+    fn save(&self, name: &str, content: &[u8]) -> io::Result<()>;
+    /// returns None if no file was saved with this name.
+    fn load(&self, name: &str) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// Backend used to store the labels, selected in the configuration.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum StorageConfig {
+    // directory on the local filesystem
+    Local { dir: PathBuf },
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig::Local {
+            dir: PathBuf::from("/var/lib/mondialrelay/labels"),
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn build(&self) -> io::Result<Arc<dyn LabelStorage>> {
+        match self {
+            StorageConfig::Local { dir } => Ok(Arc::new(LocalStorage::new(dir)?)),
+        }
+    }
+}
+
+pub struct LocalStorage {
+    dir: PathBuf,
+}
+
+impl LocalStorage {
+    /// the directory is created if it doesn't exist.
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(LocalStorage {
+            dir: dir.to_path_buf(),
+        })
+    }
+}
+
+impl LabelStorage for LocalStorage {
+    fn save(&self, name: &str, content: &[u8]) -> io::Result<()> {
+        // write to a temporary file first, so a label is never read half written.
+        let tmp = self.dir.join(format!(".{name}.tmp"));
+        fs::write(&tmp, content)?;
+        fs::rename(tmp, self.dir.join(name))
+    }
+    fn load(&self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.dir.join(name)) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,158 @@
+// Follow the shipments on the tracing of Mondial Relay until they are delivered,
+// keeping every event so support can answer without opening the site of Mondial Relay.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl,
+    SelectableHelper,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    AppState,
+    db::{
+        model::{Shipment, ShipmentEvent},
+        schema::{shipment_events, shipments},
+    },
+    error::AppError,
+    webhook::{self, WebhookEvent},
+    webservice::{self, DELIVERED},
+};
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct TrackerConfig {
+    // query the tracing in the background, needs the web service
+    pub enabled: bool,
+    // seconds between two rounds of tracing
+    pub interval: u64,
+    // days after which a shipment not delivered is not followed anymore
+    pub max_age: u32,
+}
+
+impl Default for TrackerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: 3600,
+            max_age: 60,
+        }
+    }
+}
+
+/// query the tracing of the shipments at the interval of the configuration, forever.
+/// The tracker can be enabled or disabled by a reload, not its interval.
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(state.config().tracker.interval));
+    loop {
+        interval.tick().await;
+        if !state.config().tracker.enabled {
+            continue;
+        }
+        match track_shipments(&state).await {
+            Ok(count) => debug!("Tracing of {count} shipment(s) updated"),
+            Err(e) => warn!("Could not update the tracing of the shipments: {e}"),
+        }
+    }
+}
+
+/// update the status and events of the shipments not delivered yet.
+/// returns the number of shipments updated.
+pub async fn track_shipments(state: &AppState) -> Result<usize, AppError> {
+    let since = Utc::now() - chrono::Duration::days(state.config().tracker.max_age.into());
+    let conn = state.pool.get().await?;
+    let followed = conn
+        .interact(move |conn| {
+            shipments::table
+                .filter(
+                    shipments::tracking_status
+                        .is_null()
+                        .or(shipments::tracking_status.ne(DELIVERED)),
+                )
+                .filter(shipments::created_at.ge(since))
+                .select((
+                    shipments::id,
+                    shipments::tracking_number,
+                    shipments::tracking_status,
+                    shipments::tenant,
+                ))
+                .load::<(i32, String, Option<i32>, Option<String>)>(conn)
+        })
+        .await??;
+    let mut updated = 0;
+    for (id, tracking, status, tenant) in followed {
+        // a failing shipment must not prevent the update of the others.
+        match track_shipment(state, id, &tracking, status, tenant.as_deref()).await {
+            Ok(()) => updated += 1,
+            Err(e) => warn!("Could not update the tracing of shipment n°{id}: {e}"),
+        }
+    }
+    Ok(updated)
+}
+
+/// update the status and events of the shipment with this tracking number, delivered or not.
+pub async fn track_number(state: &AppState, number: &str) -> Result<(), AppError> {
+    let conn = state.pool.get().await?;
+    let tracking = number.to_string();
+    let (id, status, tenant) = conn
+        .interact(move |conn| {
+            shipments::table
+                .filter(shipments::tracking_number.eq(tracking))
+                .select((shipments::id, shipments::tracking_status, shipments::tenant))
+                .first::<(i32, Option<i32>, Option<String>)>(conn)
+                .optional()
+        })
+        .await??
+        .ok_or(AppError::TrackingNotFound)?;
+    track_shipment(state, id, number, status, tenant.as_deref()).await
+}
+
+async fn track_shipment(
+    state: &AppState,
+    id: i32,
+    tracking: &str,
+    previous: Option<i32>,
+    tenant: Option<&str>,
+) -> Result<(), AppError> {
+    // the tracing is only given to the brand of the shipment.
+    let config = state.config_of(tenant)?;
+    let tracing = webservice::tracing(&state.client, &config.web_service, tracking).await?;
+    let events = tracing
+        .events
+        .into_iter()
+        .map(|event| ShipmentEvent {
+            shipment_id: id,
+            label: event.label,
+            happened_at: event.happened_at,
+            location: event.location,
+            relay: event.relay,
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    let conn = state.pool.get().await?;
+    let shipment = conn
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                // the whole history is given each time, only new events are added.
+                diesel::insert_into(shipment_events::table)
+                    .values(events)
+                    .on_conflict_do_nothing()
+                    .execute(conn)?;
+                diesel::update(shipments::table.find(id))
+                    .set(shipments::tracking_status.eq(tracing.status))
+                    .returning(Shipment::as_returning())
+                    .get_result(conn)
+            })
+        })
+        .await??;
+    if previous != Some(tracing.status) {
+        webhook::notify(state, WebhookEvent::StatusChanged, &shipment).await;
+        if tracing.status == DELIVERED {
+            webhook::notify(state, WebhookEvent::Delivered, &shipment).await;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,273 @@
+// Notifications of the events of the shipments to other services, like the order system.
+// Notifications are kept in the database until they are delivered, and retried with an exponential backoff.
+
+use std::{path::PathBuf, time::Duration};
+
+use axum::http::header::CONTENT_TYPE;
+use chrono::Utc;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl, SelectableHelper};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::{
+    AppState,
+    db::{
+        model::{Shipment, WebhookDelivery},
+        schema::webhook_deliveries,
+    },
+    error::AppError,
+};
+
+/// header with the event of the notification.
+pub const EVENT_HEADER: &str = "X-Mondialrelay-Event";
+/// header with the HMAC-SHA256 of the body, as sha256=<hex>.
+pub const SIGNATURE_HEADER: &str = "X-Mondialrelay-Signature";
+
+// time given to a delivery before it can be claimed again.
+const LEASE: i64 = 300;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    ShipmentCreated,
+    // the pdf is kept by the server, or the code for printers is available
+    LabelAvailable,
+    StatusChanged,
+    Delivered,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::ShipmentCreated => "shipment_created",
+            WebhookEvent::LabelAvailable => "label_available",
+            WebhookEvent::StatusChanged => "status_changed",
+            WebhookEvent::Delivered => "delivered",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct WebhooksConfig {
+    // endpoints notified of the events
+    pub subscriptions: Vec<Subscription>,
+    // seconds between two rounds of retries
+    pub interval: u64,
+    // seconds before the first retry, doubled at each attempt
+    pub backoff: u64,
+    // attempts after which a notification is abandoned
+    pub max_attempts: i32,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            subscriptions: vec![],
+            interval: 30,
+            backoff: 30,
+            max_attempts: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Subscription {
+    pub url: Url,
+    // pass path of the secret signing the notifications
+    pub secret_path: PathBuf,
+    // events notified, all if empty
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+}
+
+// body of a notification.
+#[derive(Serialize)]
+struct Notification<'a> {
+    event: WebhookEvent,
+    shipment: &'a Shipment,
+}
+
+/// queue the notification of the event to the subscriptions, and try to deliver it right away.
+/// Errors are only logged, a notification must not fail the operation notified.
+pub async fn notify(state: &AppState, event: WebhookEvent, shipment: &Shipment) {
+    if state.config().webhooks.subscriptions.is_empty() {
+        return;
+    }
+    if let Err(e) = enqueue(state, event, shipment).await {
+        warn!(
+            "Could not queue the notification {} of shipment n°{}: {e}",
+            event.as_str(),
+            shipment.id
+        );
+        return;
+    }
+    let state = state.clone();
+    tokio::spawn(async move {
+        if let Err(e) = deliver_pending(&state).await {
+            warn!("Could not deliver the notifications: {e}");
+        }
+    });
+}
+
+async fn enqueue(
+    state: &AppState,
+    event: WebhookEvent,
+    shipment: &Shipment,
+) -> Result<(), AppError> {
+    let payload = serde_json::to_string(&Notification { event, shipment })
+        .expect("shipment should serialize to json");
+    let deliveries = state
+        .config()
+        .webhooks
+        .subscriptions
+        .iter()
+        .filter(|subscription| {
+            subscription.events.is_empty() || subscription.events.contains(&event)
+        })
+        .map(|subscription| WebhookDelivery {
+            url: subscription.url.to_string(),
+            event: event.as_str().to_string(),
+            payload: payload.clone(),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    if deliveries.is_empty() {
+        return Ok(());
+    }
+    let conn = state.pool.get().await?;
+    conn.interact(move |conn| {
+        diesel::insert_into(webhook_deliveries::table)
+            .values(deliveries)
+            .execute(conn)
+    })
+    .await??;
+    Ok(())
+}
+
+/// retry the notifications not delivered at the interval of the configuration, forever.
+/// The subscriptions can be added or removed by a reload, not the interval.
+pub async fn run(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(state.config().webhooks.interval));
+    loop {
+        interval.tick().await;
+        if state.config().webhooks.subscriptions.is_empty() {
+            continue;
+        }
+        if let Err(e) = deliver_pending(&state).await {
+            warn!("Could not deliver the notifications: {e}");
+        }
+    }
+}
+
+/// send the notifications due, returns the number delivered.
+pub async fn deliver_pending(state: &AppState) -> Result<usize, AppError> {
+    let config = state.config();
+    let max_attempts = config.webhooks.max_attempts;
+    let conn = state.pool.get().await?;
+    let due = conn
+        .interact(move |conn| {
+            webhook_deliveries::table
+                .filter(webhook_deliveries::delivered_at.is_null())
+                .filter(webhook_deliveries::attempts.lt(max_attempts))
+                .filter(webhook_deliveries::next_attempt_at.le(Utc::now()))
+                .order(webhook_deliveries::next_attempt_at.asc())
+                .select(webhook_deliveries::id)
+                .load::<i32>(conn)
+        })
+        .await??;
+    let mut delivered = 0;
+    for id in due {
+        // the delivery can be claimed at the same time by the retries and a new notification.
+        let Some(delivery) = conn
+            .interact(move |conn| {
+                let now = Utc::now();
+                diesel::update(
+                    webhook_deliveries::table
+                        .find(id)
+                        .filter(webhook_deliveries::delivered_at.is_null())
+                        .filter(webhook_deliveries::next_attempt_at.le(now)),
+                )
+                .set(webhook_deliveries::next_attempt_at.eq(now + chrono::Duration::seconds(LEASE)))
+                .returning(WebhookDelivery::as_returning())
+                .get_result(conn)
+                .optional()
+            })
+            .await??
+        else {
+            continue;
+        };
+        let result = send(state, &delivery).await;
+        if result.is_ok() {
+            delivered += 1;
+        }
+        let attempts = delivery.attempts + 1;
+        let backoff = config.webhooks.backoff as i64 * (1 << (attempts - 1).min(16));
+        conn.interact(move |conn| {
+            let row = webhook_deliveries::table.find(id);
+            match result {
+                Ok(()) => diesel::update(row)
+                    .set((
+                        webhook_deliveries::attempts.eq(attempts),
+                        webhook_deliveries::delivered_at.eq(Utc::now()),
+                    ))
+                    .execute(conn),
+                Err(e) => diesel::update(row)
+                    .set((
+                        webhook_deliveries::attempts.eq(attempts),
+                        webhook_deliveries::last_error.eq(e),
+                        webhook_deliveries::next_attempt_at
+                            .eq(Utc::now() + chrono::Duration::seconds(backoff)),
+                    ))
+                    .execute(conn),
+            }
+        })
+        .await??;
+    }
+    Ok(delivered)
+}
+
+// post the notification, returns the error to keep for the next attempt.
+async fn send(state: &AppState, delivery: &WebhookDelivery) -> Result<(), String> {
+    let config = state.config();
+    let subscription = config
+        .webhooks
+        .subscriptions
+        .iter()
+        .find(|subscription| subscription.url.as_str() == delivery.url)
+        .ok_or("No subscription for this url anymore")?;
+    let secret = get_pass::get_password(&subscription.secret_path)
+        .map_err(|_| "Could not read the secret of the subscription")?;
+    debug!("Sending notification n°{} to {}", delivery.id, delivery.url);
+    state
+        .client
+        .post(subscription.url.clone())
+        .timeout(Duration::from_secs(30))
+        .header(CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, &delivery.event)
+        .header(
+            SIGNATURE_HEADER,
+            format!("sha256={}", signature(&secret, &delivery.payload)),
+        )
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// HMAC-SHA256 of the body with the secret of the subscription, in lowercase hex.
+pub fn signature(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
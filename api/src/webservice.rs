@@ -0,0 +1,289 @@
+// Client of the web service of Mondial Relay (SOAP API v1).
+// The API v2 only creates shipments, the tracing of the parcels and the search of relays are only available here.
+
+use std::path::PathBuf;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use url::Url;
+use xmltree::Element;
+
+use crate::{error::AppError, response::Status};
+
+const NAMESPACE: &str = "http://www.mondialrelay.fr/webservice/";
+
+/// Status of the tracing of a delivered parcel.
+pub const DELIVERED: i32 = 82;
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct WebServiceConfig {
+    // ask the web service if the relay of a shipment exists and is open before creating it
+    pub check_relay: bool,
+    // url of the web service
+    pub url: Url,
+    // brand code ("Enseigne") of the web service, it is not the brand id of the API v2.
+    pub brand_code: String,
+    // pass path of the private key of the web service
+    pub private_key_path: PathBuf,
+    // language of the events of the tracing: FR, EN, ES, NL, DE, IT, PT
+    pub language: String,
+}
+
+impl Default for WebServiceConfig {
+    // test brand of Mondial Relay
+    fn default() -> Self {
+        Self {
+            check_relay: false,
+            url: Url::parse("https://api.mondialrelay.com/Web_Services.asmx").unwrap(),
+            brand_code: "BDTEST13".to_string(),
+            private_key_path: PathBuf::from("mondialrelay/web_service_key"),
+            language: "FR".to_string(),
+        }
+    }
+}
+
+/// Tracing of a parcel.
+#[derive(Debug, Default)]
+pub struct Tracing {
+    // 80: registered, 81: in transit, 82: delivered, 83: anomaly
+    pub status: i32,
+    pub events: Vec<TracingEvent>,
+}
+
+#[derive(Debug, Default)]
+pub struct TracingEvent {
+    pub label: String,
+    // local time of Mondial Relay
+    pub happened_at: NaiveDateTime,
+    pub location: Option<String>,
+    // Point Relais of the event, with its country (e. g. FR-12345)
+    pub relay: Option<String>,
+}
+
+/// returns the tracing of the parcel with this shipment number.
+pub async fn tracing(
+    client: &Client,
+    config: &WebServiceConfig,
+    shipment_number: &str,
+) -> Result<Tracing, AppError> {
+    let result = call(
+        client,
+        config,
+        "WSI2_TracingColisDetaille",
+        &[
+            ("Enseigne", &config.brand_code),
+            ("Expedition", shipment_number),
+            ("Langue", &config.language),
+        ],
+    )
+    .await?;
+    let status = stat(&result)?;
+    // 80 to 83 are the statuses of the parcel, others are errors.
+    if !(80..=83).contains(&status) {
+        return Err(error(status));
+    }
+    let events = result
+        .get_child("Tracing")
+        .map(|tracing| {
+            tracing
+                .children
+                .iter()
+                .filter_map(|event| event.as_element())
+                .filter_map(|event| {
+                    let date = text(event, "Date")?;
+                    let time = text(event, "Heure").unwrap_or("00:00".to_string());
+                    Some(TracingEvent {
+                        label: text(event, "Libelle")?,
+                        happened_at: parse_date(&date, &time)?,
+                        location: text(event, "Emplacement"),
+                        relay: text(event, "Relais_Num").map(|relay| {
+                            match text(event, "Relais_Pays") {
+                                Some(country) => format!("{country}-{relay}"),
+                                None => relay,
+                            }
+                        }),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(Tracing { status, events })
+}
+
+/// Point Relais found by the web service.
+#[derive(Debug, Default)]
+pub struct Relay {
+    pub number: String,
+    // periods of closing, first and last day
+    pub closings: Vec<(NaiveDate, NaiveDate)>,
+}
+
+impl Relay {
+    pub fn is_closed_on(&self, day: NaiveDate) -> bool {
+        self.closings
+            .iter()
+            .any(|(start, end)| (*start..=*end).contains(&day))
+    }
+}
+
+/// returns the Point Relais with this number if it exists and allows the delivery mode.
+pub async fn find_relay(
+    client: &Client,
+    config: &WebServiceConfig,
+    country: &str,
+    number: &str,
+    delivery_mode: &str,
+) -> Result<Option<Relay>, AppError> {
+    let result = call(
+        client,
+        config,
+        "WSI4_PointRelais_Recherche",
+        &[
+            ("Enseigne", &config.brand_code),
+            ("Pays", country),
+            ("NumPointRelais", number),
+            ("Ville", ""),
+            ("CP", ""),
+            ("Latitude", ""),
+            ("Longitude", ""),
+            ("Taille", ""),
+            ("Poids", ""),
+            ("Action", delivery_mode),
+            ("DelaiEnvoi", ""),
+            ("RayonRecherche", ""),
+            ("TypeActivite", ""),
+            ("NACE", ""),
+            ("NombreResultats", ""),
+        ],
+    )
+    .await?;
+    let status = stat(&result)?;
+    if status != 0 {
+        return Err(error(status));
+    }
+    let Some(relays) = result.get_child("PointsRelais") else {
+        return Ok(None);
+    };
+    Ok(relays
+        .children
+        .iter()
+        .filter_map(|relay| relay.as_element())
+        .find(|relay| text(relay, "Num").is_some_and(|num| num == number))
+        .map(|relay| Relay {
+            number: number.to_string(),
+            closings: relay
+                .get_child("Informations_Dispo")
+                .map(|periods| {
+                    periods
+                        .children
+                        .iter()
+                        .filter_map(|period| period.as_element())
+                        .filter_map(|period| {
+                            Some((
+                                parse_day(&text(period, "Debut")?)?,
+                                parse_day(&text(period, "Fin")?)?,
+                            ))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }))
+}
+
+/// call a method of the web service, returns its result.
+/// The security key is computed from the values of the parameters, in the same order.
+pub(crate) async fn call(
+    client: &Client,
+    config: &WebServiceConfig,
+    method: &str,
+    params: &[(&str, &str)],
+) -> Result<Element, AppError> {
+    let private_key =
+        get_pass::get_password(&config.private_key_path).map_err(|_| AppError::Conf)?;
+    let mut body = String::new();
+    for (name, value) in params {
+        body.push_str(&format!("<{name}>{}</{name}>", escape(value)));
+    }
+    body.push_str(&format!(
+        "<Security>{}</Security>",
+        security(params.iter().map(|(_, value)| *value), &private_key)
+    ));
+    let envelope = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?><soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/"><soap:Body><{method} xmlns="{NAMESPACE}">{body}</{method}></soap:Body></soap:Envelope>"#
+    );
+    let resp = client
+        .post(config.url.clone())
+        .header("SOAPAction", format!("{NAMESPACE}{method}"))
+        .body(envelope)
+        .send()
+        .await?;
+    if resp.status().is_server_error() {
+        return Err(AppError::Unavailable(resp.status().to_string()));
+    }
+    let xml = resp.text().await?;
+    let envelope = Element::parse(xml.as_bytes()).map_err(|e| AppError::Xml(e.to_string()))?;
+    envelope
+        .get_child("Body")
+        .and_then(|body| body.get_child(format!("{method}Response").as_str()))
+        .and_then(|response| response.get_child(format!("{method}Result").as_str()))
+        .cloned()
+        .ok_or(AppError::Xml(format!(
+            "No result in the response of {method}"
+        )))
+}
+
+/// status of the result of a call.
+pub(crate) fn stat(result: &Element) -> Result<i32, AppError> {
+    text(result, "STAT")
+        .and_then(|stat| stat.parse().ok())
+        .ok_or(AppError::Xml("No STAT in the result".to_string()))
+}
+
+/// error for a status of the web service, which shares its codes with the API v2.
+pub(crate) fn error(status: i32) -> AppError {
+    AppError::from(&Status {
+        code: status.to_string(),
+        level: "Error".to_string(),
+        message: format!("Web service returned the status {status}"),
+    })
+}
+
+/// text of a child element, None if empty.
+pub(crate) fn text(element: &Element, name: &str) -> Option<String> {
+    element
+        .get_child(name)?
+        .get_text()
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+// uppercase md5 of the values followed by the private key.
+fn security<'a>(values: impl Iterator<Item = &'a str>, private_key: &'a str) -> String {
+    let concat = values
+        .chain(std::iter::once(private_key))
+        .collect::<String>();
+    format!("{:X}", md5::compute(concat))
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// dates are given as dd/mm/yy or dd/mm/yyyy, times as hh:mm
+fn parse_date(date: &str, time: &str) -> Option<NaiveDateTime> {
+    let datetime = format!("{date} {time}");
+    // %Y would read a year of two digits as is.
+    ["%d/%m/%y %H:%M", "%d/%m/%Y %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&datetime, format).ok())
+}
+
+// days are given as yyyy-mm-dd, followed by a time
+fn parse_day(day: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(day.get(..10)?, "%Y-%m-%d").ok()
+}
@@ -0,0 +1,266 @@
+// Local stand-in of the Mondial Relay API, so tests do not need a Mondial Relay account nor network access.
+// It validates requests with the same schema as the server and answers like Mondial Relay does.
+
+use std::sync::{
+    Mutex,
+    atomic::{AtomicU32, Ordering},
+};
+
+use axum::{
+    Router,
+    extract::State,
+    http::{HeaderMap, HeaderName, StatusCode, header::CONTENT_TYPE},
+    routing::{get, post},
+    serve,
+};
+use mondialrelay_api_lib::{
+    request::ShipmentCreationRequest,
+    response::{
+        Label, LabelList, Shipment, ShipmentCreationResponse, ShipmentsList, Status, StatusList,
+    },
+    webhook::{EVENT_HEADER, SIGNATURE_HEADER},
+};
+use url::Url;
+use xsd_parser::generator::validator::Validate;
+
+/// Relay refused by the mock, to test rejected shipments.
+pub const UNKNOWN_RELAY: &str = "FR-00000";
+/// Relay for which the mock answers with its generic error.
+pub const FAILING_RELAY: &str = "FR-99999";
+
+static SHIPMENT_NUMBER: AtomicU32 = AtomicU32::new(10000000);
+
+/// Relay closed for holidays, to test the check of relays.
+pub const CLOSED_RELAY: &str = "FR-99999";
+
+/// notification received by the mock of an order system.
+pub struct Notification {
+    pub event: String,
+    pub signature: String,
+    pub body: String,
+}
+
+static NOTIFICATIONS: Mutex<Vec<Notification>> = Mutex::new(vec![]);
+
+/// start the mock server on a random port and returns its base url, to use as api_url in the config.
+pub async fn spawn_mock() -> Url {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let root = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+    let app = Router::new()
+        .route("/api/shipment", post(shipment))
+        .route("/ww2/PDF/StickerMaker2.aspx", get(label))
+        .route("/Web_Services.asmx", post(web_service))
+        // order system receiving the webhooks, and one always failing.
+        .route("/webhook", post(webhook))
+        .route(
+            "/webhook/fail",
+            post(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+        )
+        .with_state(root.clone());
+    tokio::spawn(async move { serve(listener, app).await.unwrap() });
+    root.join("api/").unwrap()
+}
+
+async fn shipment(State(root): State<Url>, body: String) -> (StatusCode, String) {
+    let request: ShipmentCreationRequest = match yaserde::de::from_str(&body) {
+        Ok(request) => request,
+        Err(e) => return rejected("98", &e),
+    };
+    if let Err(e) = request.validate() {
+        return rejected("98", &e);
+    }
+    let mut shipments = vec![];
+    for shipment in &request.shipments_list.shipment {
+        if shipment.delivery_mode.location.as_deref() == Some(UNKNOWN_RELAY) {
+            return rejected("14", "Numéro de Relais de livraison invalide");
+        }
+        if shipment.delivery_mode.location.as_deref() == Some(FAILING_RELAY) {
+            return rejected("99", "Erreur générique du service");
+        }
+        let number = SHIPMENT_NUMBER.fetch_add(1, Ordering::Relaxed);
+        let output = if request.output_options.output_type.0 == "PdfUrl" {
+            format!(
+                "{}ww2/PDF/StickerMaker2.aspx?ens={}&expedition={}&lg={}&format={}",
+                root,
+                request.context.customer_id.0,
+                number,
+                request.context.culture.0,
+                request.output_options.output_format.0
+            )
+        } else {
+            format!("^XA^FO50,50^BCN,100,Y,N,N^FD{number}^FS^XZ")
+        };
+        shipments.push(Shipment {
+            shipment_number: number.to_string(),
+            label_list: LabelList {
+                label: vec![Label {
+                    output,
+                    raw_content: None,
+                }],
+            },
+        });
+    }
+    let response = ShipmentCreationResponse {
+        status_list: StatusList {
+            status: vec![Status {
+                code: "0".to_string(),
+                level: "Info".to_string(),
+                message: "OK".to_string(),
+            }],
+        },
+        shipments_list: Some(ShipmentsList {
+            shipment: shipments,
+        }),
+    };
+    (StatusCode::OK, yaserde::ser::to_string(&response).unwrap())
+}
+
+fn rejected(code: &str, message: &str) -> (StatusCode, String) {
+    let response = ShipmentCreationResponse {
+        status_list: StatusList {
+            status: vec![Status {
+                code: code.to_string(),
+                level: "Error".to_string(),
+                message: message.to_string(),
+            }],
+        },
+        shipments_list: None,
+    };
+    (
+        StatusCode::BAD_REQUEST,
+        yaserde::ser::to_string(&response).unwrap(),
+    )
+}
+
+async fn webhook(headers: HeaderMap, body: String) -> StatusCode {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+    NOTIFICATIONS.lock().unwrap().push(Notification {
+        event: header(EVENT_HEADER),
+        signature: header(SIGNATURE_HEADER),
+        body,
+    });
+    StatusCode::OK
+}
+
+/// notifications received for this order.
+pub fn notifications(id_order: u32) -> Vec<Notification> {
+    let pattern = format!(r#""order_id":{id_order},"#);
+    NOTIFICATIONS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|notification| notification.body.contains(&pattern))
+        .map(|notification| Notification {
+            event: notification.event.clone(),
+            signature: notification.signature.clone(),
+            body: notification.body.clone(),
+        })
+        .collect()
+}
+
+// web service (API v1), for the tracing (every parcel is delivered) and the search of relays.
+async fn web_service(body: String) -> (StatusCode, String) {
+    if body.contains("<WSI4_PointRelais_Recherche") {
+        return relay_search(&body);
+    }
+    if !body.contains("<WSI2_TracingColisDetaille") {
+        return (StatusCode::NOT_FOUND, String::new());
+    }
+    let events = [
+        ("PRISE EN CHARGE EN AGENCE", "14/10/2026", "09:12", "AGENCE DIJON", "", ""),
+        ("COLIS LIVRÉ", "16/10/2026", "15:40", "DIJON", "24738", "FR"),
+    ]
+    .iter()
+    .map(|(label, date, time, location, relay, country)| {
+        format!(
+            "<ret_WSI2_sub_TracingColisDetaille><Libelle>{label}</Libelle><Date>{date}</Date><Heure>{time}</Heure><Emplacement>{location}</Emplacement><Relais_Num>{relay}</Relais_Num><Relais_Pays>{country}</Relais_Pays></ret_WSI2_sub_TracingColisDetaille>"
+        )
+    })
+    .collect::<String>();
+    (
+        StatusCode::OK,
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?><soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/"><soap:Body><WSI2_TracingColisDetailleResponse xmlns="http://www.mondialrelay.fr/webservice/"><WSI2_TracingColisDetailleResult><STAT>82</STAT><Libelle01>COLIS LIVRÉ</Libelle01><Tracing>{events}</Tracing></WSI2_TracingColisDetailleResult></WSI2_TracingColisDetailleResponse></soap:Body></soap:Envelope>"#
+        ),
+    )
+}
+
+// every relay exists, except the unknown one.
+fn relay_search(body: &str) -> (StatusCode, String) {
+    let number = body
+        .split_once("<NumPointRelais>")
+        .and_then(|(_, rest)| rest.split_once("</NumPointRelais>"))
+        .map(|(number, _)| number)
+        .unwrap_or_default();
+    let relays = if UNKNOWN_RELAY.ends_with(number) {
+        String::new()
+    } else {
+        // closed from yesterday to tomorrow
+        let closing = if CLOSED_RELAY.ends_with(number) {
+            let today = chrono::Utc::now().date_naive();
+            format!(
+                "<Informations_Dispo><Periode><Debut>{}T00:00:00</Debut><Fin>{}T00:00:00</Fin></Periode></Informations_Dispo>",
+                today.pred_opt().unwrap(),
+                today.succ_opt().unwrap()
+            )
+        } else {
+            String::new()
+        };
+        format!(
+            "<PointsRelais><PointRelais_Details><Num>{number}</Num><LgAdr1>RELAY</LgAdr1>{closing}</PointRelais_Details></PointsRelais>"
+        )
+    };
+    (
+        StatusCode::OK,
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?><soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/"><soap:Body><WSI4_PointRelais_RechercheResponse xmlns="http://www.mondialrelay.fr/webservice/"><WSI4_PointRelais_RechercheResult><STAT>0</STAT>{relays}</WSI4_PointRelais_RechercheResult></WSI4_PointRelais_RechercheResponse></soap:Body></soap:Envelope>"#
+        ),
+    )
+}
+
+// pdf of a label, with a single page of 10x15.
+async fn label() -> ([(HeaderName, &'static str); 1], Vec<u8>) {
+    ([(CONTENT_TYPE, "application/pdf")], label_pdf(283, 425))
+}
+
+/// pdf of a label with a single page of this size in points.
+pub fn label_pdf(width: u32, height: u32) -> Vec<u8> {
+    let content = "BT /F1 24 Tf 20 380 Td (Mondial Relay) Tj ET";
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {width} {height}] /Contents 4 0 R /Resources << /Font << /F1 5 0 R >> >> >>"
+        ),
+        format!(
+            "<< /Length {} >>\nstream\n{content}\nendstream",
+            content.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+    ];
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend(format!("{} 0 obj\n{object}\nendobj\n", i + 1).as_bytes());
+    }
+    let xref = pdf.len();
+    pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        pdf.extend(format!("{offset:010} 00000 n \n").as_bytes());
+    }
+    pdf.extend(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            objects.len() + 1
+        )
+        .as_bytes(),
+    );
+    pdf
+}
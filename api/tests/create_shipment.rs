@@ -1,35 +1,893 @@
-// test to check that a request with valid data will produce a valid response on Mondial Relay API.
+// test to check that a request with valid data will produce a valid response on the Mondial Relay API,
+// and that rejections from Mondial Relay are given back to the client.
+// Mondial Relay is replaced by the mock of the common module.
 
-use std::path::PathBuf;
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
+use axum::http::{StatusCode, header::CONTENT_TYPE};
 use axum_test::TestServer;
 use deadpool_diesel::postgres::Pool;
-use diesel::RunQueryDsl;
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl, SelectableHelper, TextExpressionMethods};
 use get_pass::get_password;
 use mondialrelay_api_lib::{
     AppState,
-    config::{AddressBusiness, Config},
-    db::schema::shipments,
-    handler::NewShipment,
+    config::{AddressBusiness, Config, ConfigSource, TenantConfig},
+    db::{
+        model::{ShipmentRequest, WebhookDelivery},
+        schema::{idempotency_keys, shipment_requests, shipments, webhook_deliveries},
+    },
+    handler::{IDEMPOTENCY_KEY, NewParcel, NewShipment, NewValue, TENANT_HEADER},
     request::{
         Address,
         address_type::{City, CountryCode, Firstname, HouseNo, Lastname, PostCode, Title},
+        output_options_type::LabelType,
+        product_configuration_type::{CollectionMode, DeliveryMode},
     },
     router,
+    sheet::{Imposition, merge_labels},
+    storage::StorageConfig,
+    tracker,
+    webhook::{self, Subscription, WebhookEvent},
+    webservice::WebServiceConfig,
 };
+use serde_json::{Value, json};
+
+mod common;
 
 #[tokio::test]
 // requirements: having a postgresql db, create db mondialrelay and dev user with password available in pass at mondial/db/test. Having the .env file in the api crate with the DATABASE_URL var set.
 async fn correct_response() -> Result<(), Box<dyn std::error::Error>> {
-    let request = NewShipment {
-        id_order: 1,
-        delivery_mode: "24R".into(),
-        delivery_location: Some("FR-24738".into()),
+    let config = config().await;
+    let id_order = new_order(&config).await;
+    let app = app(&config).await;
+    let tracking = app
+        .post("/shipment")
+        .json(&request(id_order, "FR-24738"))
+        .expect_success()
+        .await
+        .text();
+    app.get(&format!("/label/{id_order}"))
+        .expect_success()
+        .await;
+    let pdf = app
+        .get(&format!("/label/{id_order}/pdf"))
+        .expect_success()
+        .await;
+    assert_eq!(pdf.header(CONTENT_TYPE), "application/pdf");
+    assert!(pdf.as_bytes().starts_with(b"%PDF"));
+    app.get(&format!("/tracking/{tracking}"))
+        .expect_success()
+        .await;
+    let sheet = app
+        .post("/labels")
+        .json(&json!({"id_orders": [id_order], "imposition": "four"}))
+        .expect_success()
+        .await;
+    assert!(sheet.as_bytes().starts_with(b"%PDF"));
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn idempotent_creation() -> Result<(), Box<dyn std::error::Error>> {
+    let config = config().await;
+    let id_order = new_order(&config).await;
+    let key = format!("order-{id_order}");
+    let app = app(&config).await;
+    let first = app
+        .post("/shipment")
+        .add_header(IDEMPOTENCY_KEY, &key)
+        .json(&request(id_order, "FR-24738"))
+        .expect_success()
+        .await
+        .text();
+    let retry = app
+        .post("/shipment")
+        .add_header(IDEMPOTENCY_KEY, &key)
+        .json(&request(id_order, "FR-24738"))
+        .expect_success()
+        .await
+        .text();
+    assert_eq!(first, retry);
+    app.get(&format!("/label/{id_order}"))
+        .expect_success()
+        .await
+        .assert_json(&[first_label(&config, id_order).await]);
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn stale_idempotency_key() -> Result<(), Box<dyn std::error::Error>> {
+    let config = config().await;
+    let id_order = new_order(&config).await;
+    let key = format!("order-{id_order}");
+    let app = app(&config).await;
+    // reservation of a creation interrupted long ago.
+    let conn = pool(&config).await.get().await?;
+    let reserved = key.clone();
+    conn.interact(move |conn| {
+        diesel::insert_into(idempotency_keys::table)
+            .values((
+                idempotency_keys::key.eq(reserved),
+                idempotency_keys::order_id.eq(id_order as i32),
+                idempotency_keys::created_at.eq(chrono::Utc::now() - chrono::Duration::hours(1)),
+            ))
+            .execute(conn)
+    })
+    .await??;
+    let first = app
+        .post("/shipment")
+        .add_header(IDEMPOTENCY_KEY, &key)
+        .json(&request(id_order, "FR-24738"))
+        .expect_success()
+        .await
+        .text();
+    let retry = app
+        .post("/shipment")
+        .add_header(IDEMPOTENCY_KEY, &key)
+        .json(&request(id_order, "FR-24738"))
+        .expect_success()
+        .await
+        .text();
+    assert_eq!(first, retry);
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn idempotency_key_after_service_error() -> Result<(), Box<dyn std::error::Error>> {
+    let config = config().await;
+    let id_order = new_order(&config).await;
+    let key = format!("order-{id_order}");
+    let app = app(&config).await;
+    let response = app
+        .post("/shipment")
+        .add_header(IDEMPOTENCY_KEY, &key)
+        .json(&request(id_order, common::FAILING_RELAY))
+        .expect_failure()
+        .await;
+    assert_eq!(response.json::<Value>()["code"], "UPSTREAM_UNAVAILABLE");
+    // nothing was created, the key is free for the retry.
+    app.post("/shipment")
+        .add_header(IDEMPOTENCY_KEY, &key)
+        .json(&request(id_order, "FR-24738"))
+        .expect_success()
+        .await;
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn upstream_not_found() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = config().await;
+    let id_order = new_order(&config).await;
+    // the mock answers an empty 404 to other paths.
+    config.api_url_sandbox = config.api_url_sandbox.join("../missing/")?;
+    let app = app(&config).await;
+    let response = app
+        .post("/shipment")
+        .json(&request(id_order, "FR-24738"))
+        .expect_failure()
+        .await;
+    response.assert_status(StatusCode::BAD_GATEWAY);
+    assert_eq!(response.json::<Value>()["code"], "UPSTREAM_REJECTED");
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn printer_label() -> Result<(), Box<dyn std::error::Error>> {
+    let config = config().await;
+    let id_order = new_order(&config).await;
+    let app = app(&config).await;
+    let mut request = request(id_order, "FR-24738");
+    request.output_type = Some(LabelType::ZplCode);
+    request.output_format = Some("Monarch9855".into());
+    app.post("/shipment").json(&request).expect_success().await;
+    let response = app
+        .get(&format!("/label/{id_order}/code"))
+        .expect_success()
+        .await;
+    assert_eq!(response.header(CONTENT_TYPE), "application/x-zpl");
+    assert!(response.text().starts_with("^XA"));
+    // the order has no label url.
+    let response = app
+        .get(&format!("/label/{id_order}"))
+        .expect_failure()
+        .await;
+    assert_eq!(response.json::<Value>()["code"], "ORDER_NOT_FOUND");
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn rejected_relay() -> Result<(), Box<dyn std::error::Error>> {
+    let config = config().await;
+    let id_order = new_order(&config).await;
+    let app = app(&config).await;
+    let response = app
+        .post("/shipment")
+        .json(&request(id_order, common::UNKNOWN_RELAY))
+        .expect_failure()
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(response.json::<Value>()["code"], "INVALID_RELAY");
+    // the failed attempt is kept, without the credentials.
+    let audits = requests_of(&config, id_order).await;
+    assert!(
+        audits
+            .iter()
+            .any(|audit| audit.error.is_some() && audit.request_xml.contains("********"))
+    );
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn batch_creation() -> Result<(), Box<dyn std::error::Error>> {
+    let config = config().await;
+    let id_orders = [
+        new_order(&config).await,
+        new_order(&config).await,
+        new_order(&config).await,
+    ];
+    let app = app(&config).await;
+    // the rejected shipment doesn't prevent the creation of the others.
+    let results = app
+        .post("/shipments")
+        .json(&[
+            request(id_orders[0], "FR-24738"),
+            request(id_orders[1], common::UNKNOWN_RELAY),
+            request(id_orders[2], "FR-24738"),
+        ])
+        .expect_success()
+        .await
+        .json::<Value>();
+    assert!(results[0]["tracking_number"].is_string());
+    assert_eq!(results[1]["error"]["code"], "INVALID_RELAY");
+    assert!(results[2]["tracking_number"].is_string());
+    assert!(first_label(&config, id_orders[2]).await.is_some());
+    for id_order in id_orders {
+        delete_order(&config, id_order).await;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn insured_shipment() -> Result<(), Box<dyn std::error::Error>> {
+    let config = config().await;
+    let id_order = new_order(&config).await;
+    let app = app(&config).await;
+    let mut request = request(id_order, "FR-24738");
+    request.value = Some(NewValue {
+        amount: 50000,
+        currency: "EUR".into(),
+    });
+    request.insurance = Some(2);
+    app.post("/shipment").json(&request).expect_success().await;
+    request.options.insert("XYZ".into(), "1".into());
+    let response = app.post("/shipment").json(&request).expect_failure().await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(response.json::<Value>()["code"], "INVALID_OPTION");
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn delivery_mode_rules() -> Result<(), Box<dyn std::error::Error>> {
+    let config = config().await;
+    let id_order = new_order(&config).await;
+    let app = app(&config).await;
+    // refused before calling Mondial Relay.
+    let mut heavy = request(id_order, "FR-24738");
+    heavy.parcels[0].weight = 40_000;
+    let response = app.post("/shipment").json(&heavy).expect_failure().await;
+    assert_eq!(response.json::<Value>()["code"], "WEIGHT_OUT_OF_RANGE");
+    let mut home = request(id_order, "FR-24738");
+    home.delivery_mode = DeliveryMode::Home;
+    let response = app.post("/shipment").json(&home).expect_failure().await;
+    assert_eq!(response.json::<Value>()["code"], "INVALID_RELAY");
+    let mut typo = serde_json::to_value(request(id_order, "FR-24738"))?;
+    typo["delivery_mode"] = "24T".into();
+    let response = app.post("/shipment").json(&typo).expect_failure().await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(response.json::<Value>()["code"], "INVALID_BODY");
+    let response = app
+        .post("/shipment")
+        .bytes(format!("{{\"id_order\": {id_order},").into())
+        .content_type("application/json")
+        .expect_failure()
+        .await;
+    assert_eq!(response.json::<Value>()["code"], "INVALID_BODY");
+    assert!(requests_of(&config, id_order).await.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn relay_collection() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = config().await;
+    let id_order = new_order(&config).await;
+    let mut request = request(id_order, "FR-24738");
+    request.collection_mode = Some(CollectionMode::Relay);
+    // the sender has no Point Relais to drop off the parcels.
+    let app = app(&config).await;
+    let response = app.post("/shipment").json(&request).expect_failure().await;
+    assert_eq!(response.json::<Value>()["code"], "INVALID_MODE");
+    config.address_sender.collection_relay = Some("FR-24738".into());
+    let app = self::app(&config).await;
+    app.post("/shipment").json(&request).expect_success().await;
+    let audits = requests_of(&config, id_order).await;
+    assert!(audits[0].request_xml.contains(r#"Mode="REL""#));
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn return_label() -> Result<(), Box<dyn std::error::Error>> {
+    let config = config().await;
+    let id_order = new_order(&config).await;
+    let app = app(&config).await;
+    let original = app
+        .post("/shipment")
+        .json(&request(id_order, "FR-24738"))
+        .expect_success()
+        .await
+        .text();
+    // dropped off at the Point Relais of the delivery, concurrent requests give the same return.
+    let url = format!("/return/{id_order}");
+    let (first, second) = tokio::join!(app.post(&url), app.post(&url));
+    first.assert_status_ok();
+    let tracking = first.text();
+    assert_eq!(second.text(), tracking);
+    let shipment = app
+        .get(&format!("/tracking/{tracking}"))
+        .expect_success()
+        .await
+        .json::<Value>();
+    assert_eq!(shipment["is_return"], true);
+    let original = app
+        .get(&format!("/tracking/{original}"))
+        .expect_success()
+        .await
+        .json::<Value>();
+    assert_eq!(shipment["return_of"], original["id"]);
+    // the labels of the order stay those of the shipment.
+    let labels = app
+        .get(&format!("/label/{id_order}"))
+        .expect_success()
+        .await
+        .json::<Vec<String>>();
+    assert_eq!(labels, [original["label_url"].as_str().unwrap()]);
+    // a retry gives back the same return, without asking Mondial Relay.
+    let sent = requests_of(&config, id_order).await.len();
+    let retry = app.post(&url).expect_success().await.text();
+    assert_eq!(retry, tracking);
+    let audits = requests_of(&config, id_order).await;
+    assert_eq!(audits.len(), sent);
+    assert!(
+        audits
+            .iter()
+            .any(|audit| audit.request_xml.contains(r#"Mode="LCC""#))
+    );
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn return_many_parcels() -> Result<(), Box<dyn std::error::Error>> {
+    let config = config().await;
+    let id_order = new_order(&config).await;
+    let app = app(&config).await;
+    let mut home = request(id_order, "FR-24738");
+    home.delivery_mode = DeliveryMode::Home;
+    home.delivery_location = None;
+    home.parcels.push(home.parcels[0].clone());
+    app.post("/shipment").json(&home).expect_success().await;
+    // refused before calling Mondial Relay.
+    let response = app
+        .post(&format!("/return/{id_order}"))
+        .json(&json!({"collection_location": "FR-24738"}))
+        .expect_failure()
+        .await;
+    assert_eq!(response.json::<Value>()["code"], "INVALID_PARCEL");
+    assert_eq!(requests_of(&config, id_order).await.len(), 1);
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn tracing_events() -> Result<(), Box<dyn std::error::Error>> {
+    let config = config().await;
+    let id_order = new_order(&config).await;
+    let state = AppState::new(config.clone()).await?;
+    let app = TestServer::new(router(state.clone()))?;
+    let tracking = app
+        .post("/shipment")
+        .json(&request(id_order, "FR-24738"))
+        .expect_success()
+        .await
+        .text();
+    // the whole history is given at each tracing, events must not be duplicated.
+    tracker::track_number(&state, &tracking).await?;
+    tracker::track_number(&state, &tracking).await?;
+    let shipment = app
+        .get(&format!("/tracking/{tracking}"))
+        .expect_success()
+        .await
+        .json::<Value>();
+    assert_eq!(shipment["tracking_status"], 82);
+    let events = app
+        .get(&format!("/shipment/{}/events", shipment["id"]))
+        .expect_success()
+        .await
+        .json::<Value>();
+    assert_eq!(events.as_array().map(Vec::len), Some(2));
+    assert_eq!(events[1]["relay"], "FR-24738");
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn webhook_notifications() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = config().await;
+    let id_order = new_order(&config).await;
+    let root = config.api_url.join("../")?;
+    let failing = root.join("webhook/fail")?;
+    config.webhooks.subscriptions = vec![
+        Subscription {
+            url: root.join("webhook")?,
+            secret_path: config.db_pass_path.clone(),
+            events: vec![],
+        },
+        Subscription {
+            url: failing.clone(),
+            secret_path: config.db_pass_path.clone(),
+            events: vec![WebhookEvent::ShipmentCreated],
+        },
+    ];
+    let app = app(&config).await;
+    let mut request = request(id_order, "FR-24738");
+    request.output_type = Some(LabelType::ZplCode);
+    request.output_format = Some("Monarch9855".into());
+    app.post("/shipment").json(&request).expect_success().await;
+    // notifications are sent in the background.
+    let mut received = vec![];
+    for _ in 0..50 {
+        received = common::notifications(id_order);
+        if received.len() >= 2 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    let events = received
+        .iter()
+        .map(|n| n.event.as_str())
+        .collect::<Vec<_>>();
+    assert!(events.contains(&"shipment_created"));
+    assert!(events.contains(&"label_available"));
+    let secret = get_password(&config.db_pass_path)?;
+    for notification in &received {
+        assert_eq!(
+            notification.signature,
+            format!("sha256={}", webhook::signature(&secret, &notification.body))
+        );
+    }
+    // the failed notification is kept to be retried later.
+    let conn = pool(&config).await.get().await?;
+    let url = failing.to_string();
+    let payload = format!(r#"%"order_id":{id_order},%"#);
+    let deliveries = payload.clone();
+    let failed = conn
+        .interact(move |conn| {
+            webhook_deliveries::table
+                .filter(webhook_deliveries::url.eq(url))
+                .filter(webhook_deliveries::payload.like(deliveries))
+                .select(WebhookDelivery::as_select())
+                .load(conn)
+        })
+        .await??;
+    assert_eq!(failed.len(), 1);
+    assert!(failed[0].delivered_at.is_none());
+    conn.interact(move |conn| {
+        diesel::delete(webhook_deliveries::table.filter(webhook_deliveries::payload.like(payload)))
+            .execute(conn)
+    })
+    .await??;
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn relay_check() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = config().await;
+    let id_order = new_order(&config).await;
+    config.web_service.check_relay = true;
+    let app = app(&config).await;
+    let response = app
+        .post("/shipment")
+        .json(&request(id_order, common::CLOSED_RELAY))
+        .expect_failure()
+        .await;
+    response.assert_status(StatusCode::BAD_REQUEST);
+    assert_eq!(response.json::<Value>()["code"], "RELAY_CLOSED");
+    let response = app
+        .post("/shipment")
+        .json(&request(id_order, common::UNKNOWN_RELAY))
+        .expect_failure()
+        .await;
+    assert_eq!(response.json::<Value>()["code"], "INVALID_RELAY");
+    // refused before calling the API of Mondial Relay.
+    assert!(requests_of(&config, id_order).await.is_empty());
+    app.post("/shipment")
+        .json(&request(id_order, "FR-24738"))
+        .expect_success()
+        .await;
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn shared_references() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = config().await;
+    let id_order = new_order(&config).await;
+    let mut request = request(id_order, "FR-24738");
+    request.order_no = Some("ORDER-15".into());
+    request.customer_no = Some("C42".into());
+    // not shared by default.
+    let app = app(&config).await;
+    app.post("/shipment").json(&request).expect_success().await;
+    assert!(
+        !requests_of(&config, id_order).await[0]
+            .request_xml
+            .contains("ORDER-15")
+    );
+    delete_order(&config, id_order).await;
+    config.share_references = true;
+    let app = self::app(&config).await;
+    app.post("/shipment").json(&request).expect_success().await;
+    assert!(
+        requests_of(&config, id_order).await[0]
+            .request_xml
+            .contains("ORDER-15")
+    );
+    request.customer_no = Some("CUSTOMER-1234".into());
+    let response = app.post("/shipment").json(&request).expect_failure().await;
+    assert_eq!(response.json::<Value>()["code"], "INVALID_REFERENCE");
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[test]
+fn environment_overrides() {
+    let vars = [
+        ("MONDIALRELAY_CONFIG", "/tmp/config.toml"),
+        ("MONDIALRELAY_LISTEN_PORT", "10201"),
+        ("MONDIALRELAY_BRAND_ID", "1234"),
+        ("MONDIALRELAY_WEB_SERVICE__CHECK_RELAY", "true"),
+        ("MONDIALRELAY_ADDRESS_SENDER__COLLECTION_RELAY", "FR-24738"),
+        ("OTHER_LISTEN_PORT", "1"),
+    ]
+    .map(|(name, value)| (name.to_string(), value.to_string()));
+    let config = Config::default().with_overrides(vars).unwrap();
+    assert_eq!(config.listen_port, 10201);
+    // kept as text even if it looks like a number.
+    assert_eq!(config.brand_id, "1234");
+    assert!(config.web_service.check_relay);
+    assert_eq!(
+        config.address_sender.collection_relay.as_deref(),
+        Some("FR-24738")
+    );
+    let unknown = [("MONDIALRELAY_LISTEN".to_string(), "1".to_string())];
+    assert!(Config::default().with_overrides(unknown).is_err());
+    let invalid = [("MONDIALRELAY_LISTEN_PORT".to_string(), "port".to_string())];
+    assert!(Config::default().with_overrides(invalid).is_err());
+}
+
+#[tokio::test]
+async fn config_validation() {
+    let mut config = config().await;
+    assert!(config.validate().is_ok());
+    config.culture = "en-EN".into();
+    config.format = "A3".into();
+    config.address_sender.country_code = "FRA".into();
+    config.address_sender.streetname = "Rue du Faubourg Saint-Honoré prolongée".into();
+    config.password_path_test = "mondialrelay/missing".into();
+    // every problem is reported at once.
+    let problems = config.validate().unwrap_err();
+    assert_eq!(problems.len(), 4, "{problems:?}");
+    // both bad fields of the address are in its problem.
+    let sender = problems
+        .iter()
+        .find(|problem| problem.starts_with("Invalid address_sender"))
+        .expect("sender problem");
+    assert!(sender.contains("Streetname"), "{sender}");
+    assert!(sender.contains("CountryCode"), "{sender}");
+    config.address_sender.country_code = "FR".into();
+    config.tracker.interval = 0;
+    config.webhooks.backoff = u64::MAX;
+    let problems = config.validate().unwrap_err();
+    assert!(
+        problems
+            .iter()
+            .any(|problem| problem.starts_with("tracker.interval"))
+    );
+    assert!(
+        problems
+            .iter()
+            .any(|problem| problem.starts_with("webhooks.backoff"))
+    );
+    assert!(
+        problems
+            .iter()
+            .any(|problem| problem.contains("Streetname"))
+    );
+}
+
+#[test]
+fn two_labels_sheet() -> Result<(), Box<dyn std::error::Error>> {
+    // A5 labels are turned to be printed at full size, two on a landscape half of A4.
+    let a5 = common::label_pdf(420, 595);
+    let sheet = lopdf::Document::load_mem(&merge_labels(&[a5.clone(), a5], Imposition::Two)?)?;
+    let pages = sheet.get_pages();
+    assert_eq!(pages.len(), 1);
+    let content = String::from_utf8(sheet.get_page_content(pages[&1])?)?;
+    for line in content.lines() {
+        let matrix = line
+            .split_whitespace()
+            .skip(1)
+            .take(6)
+            .map(str::parse::<f32>)
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(matrix[0], 0.0, "{line}");
+        assert!(matrix[1] > 0.99, "{line}");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn config_reload() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = config().await;
+    let path = std::env::temp_dir().join("mondialrelay-reload/config.toml");
+    confy::store_path(&path, &config)?;
+    let state = AppState::new(config.clone())
+        .await?
+        .with_source(ConfigSource {
+            path: path.clone(),
+            arguments: vec![],
+        });
+    let app = TestServer::new(router(state.clone()))?;
+    config.format = "A5".into();
+    config.listen_port += 1;
+    confy::store_path(&path, &config)?;
+    app.post("/admin/reload")
+        .expect_success()
+        .await
+        .assert_json(&json!({"restart_required": ["listen_port"]}));
+    assert_eq!(state.config().format, "A5");
+    assert_eq!(state.config().listen_port, config.listen_port - 1);
+    // an invalid configuration is not applied.
+    config.culture = "en-EN".into();
+    confy::store_path(&path, &config)?;
+    let response = app.post("/admin/reload").expect_failure().await;
+    assert_eq!(response.json::<Value>()["code"], "INVALID_CONFIGURATION");
+    assert_eq!(state.config().culture, "fr-FR");
+    Ok(())
+}
+
+#[tokio::test]
+async fn tenant_profiles() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = config().await;
+    let id_order = new_order(&config).await;
+    let other_order = new_order(&config).await;
+    let key = format!("order-{id_order}");
+    config.tenants.insert(
+        "shop2".into(),
+        TenantConfig {
+            brand_id: "BRAND2".into(),
+            password_path: "mondialrelay/db/test".into(),
+            brand_id_test: None,
+            password_path_test: None,
+            default_warehouse: None,
+            address_sender: AddressBusiness {
+                city: "Beaune".into(),
+                ..config.address_sender.clone()
+            },
+            culture: None,
+            format: Some("A5".into()),
+            output_type: None,
+            web_service: None,
+        },
+    );
+    // in test mode, a tenant must have its own test account.
+    assert!(config.validate().is_err());
+    let shop2 = config.tenants.get_mut("shop2").unwrap();
+    shop2.brand_id_test = Some("BDTEST2".into());
+    shop2.password_path_test = Some("mondialrelay/db/test".into());
+    assert!(config.validate().is_ok());
+    let app = app(&config).await;
+    let tracking = app
+        .post("/shipment")
+        .add_header(TENANT_HEADER, "shop2")
+        .add_header(IDEMPOTENCY_KEY, &key)
+        .json(&request(id_order, "FR-24738"))
+        .expect_success()
+        .await
+        .text();
+    let audit = &requests_of(&config, id_order).await[0];
+    assert!(audit.request_xml.contains("Beaune") && audit.request_xml.contains("A5"));
+    assert!(audit.request_xml.contains("BDTEST2"));
+    // the labels of a tenant are only given to it.
+    let label = format!("/label/{id_order}");
+    app.get(&label).expect_failure().await;
+    app.get(&format!("/tracking/{tracking}"))
+        .expect_failure()
+        .await;
+    let shipment = app
+        .get(&format!("/tracking/{tracking}"))
+        .add_header(TENANT_HEADER, "shop2")
+        .expect_success()
+        .await
+        .json::<Value>();
+    assert_eq!(shipment["tenant"], "shop2");
+    app.get(&label)
+        .add_header(TENANT_HEADER, "shop2")
+        .expect_success()
+        .await;
+    let response = app
+        .get(&label)
+        .add_header(TENANT_HEADER, "shop3")
+        .expect_failure()
+        .await;
+    assert_eq!(response.json::<Value>()["code"], "UNKNOWN_TENANT");
+    // the body can not choose another tenant than the header.
+    let mut other = request(id_order, "FR-24738");
+    other.tenant = Some("shop3".into());
+    let response = app
+        .post("/shipment")
+        .add_header(TENANT_HEADER, "shop2")
+        .json(&other)
+        .expect_failure()
+        .await;
+    assert_eq!(response.json::<Value>()["code"], "TENANT_MISMATCH");
+    // the keys of the default profile never match those of a tenant.
+    let other = app
+        .post("/shipment")
+        .add_header(IDEMPOTENCY_KEY, format!("shop2/{key}"))
+        .json(&request(other_order, "FR-24738"))
+        .expect_success()
+        .await
+        .text();
+    assert_ne!(other, tracking);
+    delete_order(&config, id_order).await;
+    delete_order(&config, other_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn sender_warehouses() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = config().await;
+    let id_order = new_order(&config).await;
+    config.warehouses.insert(
+        "beaune".into(),
+        AddressBusiness {
+            city: "Beaune".into(),
+            post_code: "21200".into(),
+            ..config.address_sender.clone()
+        },
+    );
+    assert!(config.validate().is_ok());
+    let app = app(&config).await;
+    let mut request = request(id_order, "FR-24738");
+    request.warehouse = Some("auxerre".into());
+    let response = app.post("/shipment").json(&request).expect_failure().await;
+    assert_eq!(response.json::<Value>()["code"], "UNKNOWN_WAREHOUSE");
+    request.warehouse = Some("beaune".into());
+    let tracking = app
+        .post("/shipment")
+        .json(&request)
+        .expect_success()
+        .await
+        .text();
+    let shipment = app
+        .get(&format!("/tracking/{tracking}"))
+        .expect_success()
+        .await
+        .json::<Value>();
+    assert_eq!(shipment["warehouse"], "beaune");
+    assert!(
+        requests_of(&config, id_order).await[0]
+            .request_xml
+            .contains("Beaune")
+    );
+    // the return goes back to the warehouse.
+    app.post(&format!("/return/{id_order}"))
+        .expect_success()
+        .await;
+    let audits = requests_of(&config, id_order).await;
+    assert!(
+        audits
+            .iter()
+            .all(|audit| audit.request_xml.contains("Beaune"))
+    );
+    app.post("/labels")
+        .json(&json!({"id_orders": [id_order], "warehouse": "auxerre"}))
+        .expect_failure()
+        .await;
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn tenant_default_warehouse() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = config().await;
+    let id_order = new_order(&config).await;
+    config.warehouses.insert(
+        "beaune".into(),
+        AddressBusiness {
+            city: "Beaune".into(),
+            ..config.address_sender.clone()
+        },
+    );
+    config.default_warehouse = Some("beaune".into());
+    config.tenants.insert(
+        "shop2".into(),
+        TenantConfig {
+            brand_id: "BRAND2".into(),
+            password_path: "mondialrelay/db/test".into(),
+            brand_id_test: Some("BDTEST2".into()),
+            password_path_test: Some("mondialrelay/db/test".into()),
+            address_sender: AddressBusiness {
+                city: "Semur".into(),
+                ..config.address_sender.clone()
+            },
+            default_warehouse: None,
+            culture: None,
+            format: None,
+            output_type: None,
+            web_service: None,
+        },
+    );
+    assert!(config.validate().is_ok());
+    let app = app(&config).await;
+    // the default warehouse of the default profile is not the one of the tenant.
+    let tracking = app
+        .post("/shipment")
+        .add_header(TENANT_HEADER, "shop2")
+        .json(&request(id_order, "FR-24738"))
+        .expect_success()
+        .await
+        .text();
+    let shipment = app
+        .get(&format!("/tracking/{tracking}"))
+        .add_header(TENANT_HEADER, "shop2")
+        .expect_success()
+        .await
+        .json::<Value>();
+    assert_eq!(shipment["warehouse"], Value::Null);
+    let audit = &requests_of(&config, id_order).await[0];
+    assert!(audit.request_xml.contains("Semur") && !audit.request_xml.contains("Beaune"));
+    delete_order(&config, id_order).await;
+    Ok(())
+}
+
+fn request(id_order: u32, relay: &str) -> NewShipment {
+    NewShipment {
+        id_order,
+        delivery_mode: DeliveryMode::Relay,
+        collection_mode: None,
+        delivery_location: Some(relay.into()),
         delivery_instructions: None,
-        length: 15,
-        width: 10,
-        depth: 5,
-        weight: 150,
+        parcels: vec![NewParcel {
+            length: 15,
+            width: 10,
+            depth: 5,
+            weight: 150,
+            content: None,
+        }],
         recipient_details: Address {
             title: Some(Title("Mr".into())),
             firstname: Some(Firstname("John".into())),
@@ -41,17 +899,60 @@ async fn correct_response() -> Result<(), Box<dyn std::error::Error>> {
             city: City("Dijon".into()),
             ..Default::default()
         },
-    };
+        output_type: None,
+        output_format: None,
+        value: None,
+        insurance: None,
+        options: Default::default(),
+        order_no: None,
+        customer_no: None,
+        tenant: None,
+        warehouse: None,
+    }
+}
+
+// the tests run at the same time on the same database, each order id is given to one test only.
+static ORDER_ID: AtomicU32 = AtomicU32::new(1);
+
+// order of a test, cleared of the data of a previous run.
+async fn new_order(config: &Config) -> u32 {
+    let id_order = ORDER_ID.fetch_add(1, Ordering::Relaxed);
+    delete_order(config, id_order).await;
+    id_order
+}
+
+// server of the api with this config.
+async fn app(config: &Config) -> TestServer {
+    let state = AppState::new(config.clone())
+        .await
+        .expect("Should create the state of the api.");
+    TestServer::new(router(state)).unwrap()
+}
+
+// config pointing to a new mock of Mondial Relay.
+async fn config() -> Config {
     // load env file.
     let db_uri = dotenv::var("DATABASE_URL")
         .expect("Should have an .env file for the test database url.")
         .parse()
         .unwrap();
-    let config = Config {
+    let api_url = common::spawn_mock().await;
+    Config {
         db_uri,
         db_pass_path: "mondialrelay/db/test".into(),
-        password_path_test: PathBuf::from("mondialrelay/test_api_key"),
+        // the mock doesn't check credentials, any existing pass entry will do.
+        password_path_test: PathBuf::from("mondialrelay/db/test"),
         test: true,
+        api_url: api_url.clone(),
+        api_url_sandbox: api_url.clone(),
+        label_storage: StorageConfig::Local {
+            dir: std::env::temp_dir().join("mondialrelay-labels"),
+        },
+        web_service: WebServiceConfig {
+            url: api_url.join("../Web_Services.asmx").unwrap(),
+            private_key_path: PathBuf::from("mondialrelay/db/test"),
+            ..Default::default()
+        },
         address_sender: AddressBusiness {
             name_business: "Dupond".to_string(),
             streetname: "Rue du Berceau".into(),
@@ -61,41 +962,67 @@ async fn correct_response() -> Result<(), Box<dyn std::error::Error>> {
             city: "Cityname".into(),
             phone_no: "".into(),
             email: "test@example.com".into(),
+            collection_relay: None,
         },
         ..Default::default()
-    };
-    // delete the tables at the beginning, in case it wasn't cleaned.
-    delete_tables(&config).await;
-    let state = AppState::new(config.clone()).await?;
-    let app = TestServer::new(router(state))?;
-    app.post("/shipment").json(&request).expect_success().await;
-    delete_tables(&config).await;
-    Ok(())
+    }
 }
-// mock server with config
-// create the data for the request
 
-// make the request
-
-// check status of response
-// reset data
-async fn delete_tables(config: &Config) {
+async fn pool(config: &Config) -> Pool {
     let mut db_uri = config.db_uri.clone();
     db_uri
         .set_password(Some(
             &get_password(&config.db_pass_path).expect("Invalid utf-8"),
         ))
         .unwrap();
-    dbg!(&db_uri);
-    let pool = Pool::builder(deadpool_diesel::Manager::new(
+    Pool::builder(deadpool_diesel::Manager::new(
         db_uri.as_str(),
         deadpool_diesel::Runtime::Tokio1,
     ))
     .build()
+    .unwrap()
+}
+
+// label saved for the order
+async fn first_label(config: &Config, id_order: u32) -> Option<String> {
+    let conn = pool(config).await.get().await.unwrap();
+    conn.interact(move |conn| {
+        shipments::table
+            .filter(shipments::order_id.eq(id_order as i32))
+            .select(shipments::label_url)
+            .first(conn)
+    })
+    .await
+    .unwrap()
+    .unwrap()
+}
+
+// exchanges with Mondial Relay recorded for the order
+async fn requests_of(config: &Config, id_order: u32) -> Vec<ShipmentRequest> {
+    let conn = pool(config).await.get().await.unwrap();
+    conn.interact(move |conn| {
+        shipment_requests::table
+            .filter(shipment_requests::order_id.eq(id_order as i32))
+            .select(ShipmentRequest::as_select())
+            .load(conn)
+    })
+    .await
+    .unwrap()
+    .unwrap()
+}
+
+// reset data of an order
+async fn delete_order(config: &Config, id_order: u32) {
+    let id_order = id_order as i32;
+    let conn = pool(config).await.get().await.unwrap();
+    conn.interact(move |conn| {
+        diesel::delete(idempotency_keys::table.filter(idempotency_keys::order_id.eq(id_order)))
+            .execute(conn)?;
+        diesel::delete(shipment_requests::table.filter(shipment_requests::order_id.eq(id_order)))
+            .execute(conn)?;
+        diesel::delete(shipments::table.filter(shipments::order_id.eq(id_order))).execute(conn)
+    })
+    .await
+    .unwrap()
     .unwrap();
-    let conn = pool.get().await.unwrap();
-    conn.interact(move |conn| diesel::delete(shipments::table).execute(conn))
-        .await
-        .unwrap()
-        .unwrap();
 }